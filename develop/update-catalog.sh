@@ -0,0 +1,30 @@
+#!/usr/bin/env bash
+# Regenerate src/catalog.json from the public Aiven API
+set -euo pipefail
+
+api="https://api.aiven.io/v1"
+catalog="$(dirname "$0")/../src/catalog.json"
+
+clouds="$(curl -fsS "${api}/clouds")"
+service_types="$(curl -fsS "${api}/service_types")"
+
+jq -n \
+	--arg version "$(date -u +%Y-%m-%d)" \
+	--argjson clouds "${clouds}" \
+	--argjson types "${service_types}" '
+	def service($type; $version_field):
+		$types.service_types[$type] as $service
+		| {plans: [$service.service_plans[].service_plan] | unique}
+		+ if $version_field == null then {} else {
+			versions: [$service.user_config_schema.properties[$version_field].enum // [] | .[] | strings] | unique
+		} end;
+	{
+		version: $version,
+		clouds: [$clouds.clouds[].cloud_name] | unique,
+		services: {
+			MySQL: service("mysql"; "mysql_version"),
+			OpenSearch: service("opensearch"; "opensearch_version"),
+			PostgreSQL: service("pg"; "pg_version"),
+			Valkey: service("valkey"; null)
+		}
+	}' > "${catalog}"
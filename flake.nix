@@ -59,7 +59,9 @@
               name = "source";
               filter =
                 path: type:
-                (lib.match ".*/test_data/.+\.(json|ya?ml)" path != null) || (craneLib.filterCargoSources path type);
+                (lib.match ".*/test_data/.+\.(json|ya?ml)" path != null)
+                || (lib.match ".*/src/catalog\.json" path != null)
+                || (craneLib.filterCargoSources path type);
             };
             pname = binary-name;
             cargo-deps = craneLib.buildDepsOnly (
@@ -98,7 +100,9 @@
               rust-analyzer
 
               # Other tooling
+              curl
               earthly
+              jq
             ])
             ++ lib.optionals pkgs.stdenv.isDarwin [
               pkgs.darwin.apple_sdk.frameworks.Security
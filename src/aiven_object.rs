@@ -1,50 +1,153 @@
 use std::collections::BTreeMap;
 
+use jsonptr::{Pointer, PointerBuf};
 use kube::core::DynamicObject;
+use serde_json::{Map, Value};
 
 pub trait AivenObject {
+	fn get_kind(&self) -> Option<String>;
+	fn get_name(&self) -> Option<String>;
+	fn get_annotations(&self) -> BTreeMap<String, String>;
+	fn annotations_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["metadata", "annotations"])
+	}
+	fn get_labels(&self) -> Option<BTreeMap<String, String>>;
+	fn labels_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["metadata", "labels"])
+	}
+	/// Value at a pointer into the object, eq. /spec/userConfig/static_ips
+	fn get_pointer(&self, pointer: &Pointer) -> Option<Value>;
+
+	fn get_plan(&self) -> Option<String>;
+	fn plan_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "plan"])
+	}
+
 	fn get_cloud_name(&self) -> Option<String>;
-	fn cloud_name_path(&self) -> String {
-		"/spec/cloudName".into()
+	fn cloud_name_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "cloudName"])
 	}
 
-	fn get_team_name(&self) -> Option<String>;
-	fn get_tags(&self) -> Option<BTreeMap<String, String>>;
-	fn tags_path(&self) -> String {
-		"/spec/tags".into()
+	fn get_namespace(&self) -> Option<String>;
+	fn get_raw_tags(&self) -> Option<Map<String, Value>>;
+	/// Tags as Aiven will see them once normalized: scalars are stringified,
+	/// while nulls and nested values are left out
+	fn get_tags(&self) -> Option<BTreeMap<String, String>> {
+		self.get_raw_tags().map(|o| {
+			o.into_iter()
+				.filter_map(|(k, v)| match v {
+					Value::String(s) => Some((k, s)),
+					Value::Number(n) => Some((k, n.to_string())),
+					Value::Bool(b) => Some((k, b.to_string())),
+					_ => None,
+				})
+				.collect()
+		})
+	}
+	fn tags_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "tags"])
 	}
-	fn tag_path(&self, tag_name: &str) -> String {
-		format!("{}/{}", self.tags_path(), tag_name)
+	fn tag_path(&self, tag_name: &str) -> PointerBuf {
+		self.tags_path().with_trailing_token(tag_name)
+	}
+
+	fn get_auth_secret_ref(&self) -> Option<Map<String, Value>>;
+	fn auth_secret_ref_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "authSecretRef"])
+	}
+
+	fn get_conn_info_secret_target(&self) -> Option<Map<String, Value>>;
+	fn conn_info_secret_target_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "connInfoSecretTarget"])
 	}
 
 	fn get_termination_protection(&self) -> Option<bool>;
-	fn termination_protection_path(&self) -> String {
-		"/spec/terminationProtection".into()
+	fn termination_protection_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "terminationProtection"])
 	}
 
 	fn get_project_vpc_id(&self) -> Option<String>;
-	fn project_vpc_id_path(&self) -> String {
-		"/spec/projectVpcId".into()
+	fn project_vpc_id_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "projectVpcId"])
+	}
+
+	fn get_project_vpc_ref(&self) -> Option<Map<String, Value>>;
+
+	fn get_user_config(&self) -> Option<Map<String, Value>>;
+	fn user_config_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "userConfig"])
+	}
+	fn user_config_value_path(&self, key: &str) -> PointerBuf {
+		self.user_config_path().with_trailing_token(key)
+	}
+
+	fn get_ip_filter(&self) -> Option<Vec<Value>> {
+		self.get_user_config()
+			.and_then(|user_config| user_config.get("ip_filter").cloned())
+			.and_then(|ip_filter| ip_filter.as_array().cloned())
+	}
+	fn ip_filter_path(&self) -> PointerBuf {
+		self.user_config_value_path("ip_filter")
+	}
+
+	fn get_maintenance_window_dow(&self) -> Option<String>;
+	fn maintenance_window_dow_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "maintenanceWindowDow"])
+	}
+
+	fn get_maintenance_window_time(&self) -> Option<String>;
+	fn maintenance_window_time_path(&self) -> PointerBuf {
+		PointerBuf::from_tokens(["spec", "maintenanceWindowTime"])
 	}
 }
 
 impl AivenObject for DynamicObject {
+	fn get_kind(&self) -> Option<String> {
+		self.types.as_ref().map(|t| t.kind.clone())
+	}
+
+	fn get_name(&self) -> Option<String> {
+		self.metadata.name.clone()
+	}
+
+	fn get_annotations(&self) -> BTreeMap<String, String> {
+		self.metadata.annotations.clone().unwrap_or_default()
+	}
+
+	fn get_labels(&self) -> Option<BTreeMap<String, String>> {
+		self.metadata.labels.clone()
+	}
+
+	fn get_pointer(&self, pointer: &Pointer) -> Option<Value> {
+		pointer.resolve(&self.data).ok().cloned()
+	}
+
+	fn get_plan(&self) -> Option<String> {
+		self.data["spec"]["plan"].as_str().map(|s| s.to_string())
+	}
+
 	fn get_cloud_name(&self) -> Option<String> {
 		self.data["spec"]["cloudName"]
 			.as_str()
 			.map(|s| s.to_string())
 	}
 
-	fn get_team_name(&self) -> Option<String> {
+	fn get_namespace(&self) -> Option<String> {
 		self.metadata.namespace.clone()
 	}
 
-	fn get_tags(&self) -> Option<BTreeMap<String, String>> {
-		self.data["spec"]["tags"].as_object().map(|o| {
-			o.iter()
-				.map(|(k, v)| (k.to_owned(), v.as_str().unwrap_or("").to_string()))
-				.collect()
-		})
+	fn get_raw_tags(&self) -> Option<Map<String, Value>> {
+		self.data["spec"]["tags"].as_object().cloned()
+	}
+
+	fn get_auth_secret_ref(&self) -> Option<Map<String, Value>> {
+		self.data["spec"]["authSecretRef"].as_object().cloned()
+	}
+
+	fn get_conn_info_secret_target(&self) -> Option<Map<String, Value>> {
+		self.data["spec"]["connInfoSecretTarget"]
+			.as_object()
+			.cloned()
 	}
 
 	fn get_termination_protection(&self) -> Option<bool> {
@@ -58,4 +161,84 @@ impl AivenObject for DynamicObject {
 			.as_str()
 			.map(|s| s.to_string())
 	}
+
+	fn get_project_vpc_ref(&self) -> Option<Map<String, Value>> {
+		self.data["spec"]["projectVPCRef"].as_object().cloned()
+	}
+
+	fn get_user_config(&self) -> Option<Map<String, Value>> {
+		self.data["spec"]["userConfig"].as_object().cloned()
+	}
+
+	fn get_maintenance_window_dow(&self) -> Option<String> {
+		self.data["spec"]["maintenanceWindowDow"]
+			.as_str()
+			.map(|s| s.to_string())
+	}
+
+	fn get_maintenance_window_time(&self) -> Option<String> {
+		self.data["spec"]["maintenanceWindowTime"]
+			.as_str()
+			.map(|s| s.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use pretty_assertions::assert_eq;
+	use rstest::*;
+	use serde_json::json;
+
+	use super::*;
+
+	#[fixture]
+	fn object() -> DynamicObject {
+		serde_json::from_value(json!({
+			"apiVersion": "aiven.io/v1alpha1",
+			"kind": "Valkey",
+			"metadata": {
+				"name": "test-name",
+				"namespace": "test-namespace"
+			},
+			"spec": {}
+		}))
+		.unwrap()
+	}
+
+	#[rstest]
+	#[case::plain("team", "/spec/tags/team")]
+	#[case::slash("cost/center", "/spec/tags/cost~1center")]
+	#[case::tilde("~owner", "/spec/tags/~0owner")]
+	#[case::both("a~/b", "/spec/tags/a~0~1b")]
+	#[case::empty("", "/spec/tags/")]
+	fn tag_path_is_escaped(object: DynamicObject, #[case] tag_name: &str, #[case] expected: &str) {
+		let path = object.tag_path(tag_name);
+
+		assert_eq!(path.as_str(), expected);
+		assert_eq!(path.last().unwrap().decoded(), tag_name);
+	}
+
+	#[rstest]
+	fn get_tags_normalizes_scalars(mut object: DynamicObject) {
+		object.data["spec"]["tags"] = json!({
+			"string": "value",
+			"number": 123,
+			"float": 1.5,
+			"bool": true,
+			"null": null,
+			"nested": {"key": "value"}
+		});
+
+		let tags = object.get_tags().unwrap();
+
+		assert_eq!(
+			tags,
+			BTreeMap::from([
+				("bool".to_string(), "true".to_string()),
+				("float".to_string(), "1.5".to_string()),
+				("number".to_string(), "123".to_string()),
+				("string".to_string(), "value".to_string()),
+			])
+		);
+	}
 }
@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Catalog bundled with this release
+const BUNDLED: &str = include_str!("catalog.json");
+
+/// Clouds, plans and versions Aiven offers. Empty lists are not validated
+#[derive(Debug, Default, Deserialize, Clone, PartialEq, Eq)]
+pub struct Catalog {
+	pub version: String,
+	#[serde(default)]
+	pub clouds: BTreeSet<String>,
+	// Plans and versions per kind
+	#[serde(default)]
+	pub services: BTreeMap<String, ServiceCatalog>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, PartialEq, Eq)]
+pub struct ServiceCatalog {
+	#[serde(default)]
+	pub plans: BTreeSet<String>,
+	#[serde(default)]
+	pub versions: BTreeSet<String>,
+}
+
+impl Catalog {
+	/// Load the catalog from a file, or use the bundled one when no path is given
+	pub fn load(path: Option<&Path>) -> Result<Catalog> {
+		match path {
+			Some(path) => {
+				let content = std::fs::read_to_string(path)
+					.with_context(|| format!("unable to read catalog {}", path.display()))?;
+				serde_json::from_str(&content)
+					.with_context(|| format!("unable to parse catalog {}", path.display()))
+			},
+			None => Ok(serde_json::from_str(BUNDLED)?),
+		}
+	}
+
+	pub fn has_cloud(&self, cloud_name: &str) -> bool {
+		self.clouds.is_empty() || self.clouds.contains(cloud_name)
+	}
+
+	pub fn has_plan(&self, kind: &str, plan: &str) -> bool {
+		self.services
+			.get(kind)
+			.is_none_or(|service| service.plans.is_empty() || service.plans.contains(plan))
+	}
+
+	/// Versions offered for a kind, empty when versions are not validated
+	pub fn versions(&self, kind: &str) -> Vec<String> {
+		self.services
+			.get(kind)
+			.map(|service| service.versions.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use pretty_assertions::assert_eq;
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	fn bundled_catalog_is_valid() {
+		let catalog = Catalog::load(None).unwrap();
+
+		assert!(!catalog.version.is_empty());
+		assert!(catalog.has_cloud("google-europe-north1"));
+		assert!(!catalog.has_cloud("google-atlantis1"));
+		assert!(catalog.has_cloud("upcloud-fi-hel1"));
+		assert!(!catalog.has_cloud("upcloud-fi-hel"));
+		assert!(catalog.has_plan("Valkey", "startup-4"));
+		assert!(!catalog.has_plan("Valkey", "startup-5"));
+		assert!(catalog.versions("PostgreSQL").contains(&"17".to_string()));
+	}
+
+	#[rstest]
+	fn cloud_names_in_repo_are_in_bundled_catalog() {
+		// Deliberately unknown clouds used to test denials
+		const UNKNOWN: [&str; 2] = ["google-atlantis1", "upcloud-fi-hel"];
+		let catalog = Catalog::load(None).unwrap();
+		let cloud_name =
+			regex::Regex::new(r"\b(aws|azure|do|exoscale|google|upcloud)-[a-z0-9-]*[a-z0-9]\b")
+				.unwrap();
+		let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+		let dirs = ["src", "src/test_data", "charts/mutilator", "develop"];
+		let files = dirs
+			.iter()
+			.flat_map(|dir| std::fs::read_dir(root.join(dir)).unwrap())
+			.map(|entry| entry.unwrap().path())
+			.chain([root.join("README.md")])
+			.filter(|path| path.is_file() && !path.ends_with("catalog.json"));
+
+		let mut missing = BTreeSet::new();
+		for path in files {
+			let Ok(content) = std::fs::read_to_string(&path) else {
+				continue;
+			};
+			for name in cloud_name.find_iter(&content).map(|m| m.as_str()) {
+				if !UNKNOWN.contains(&name) && !catalog.has_cloud(name) {
+					missing.insert(format!("{} in {}", name, path.display()));
+				}
+			}
+		}
+
+		assert_eq!(missing, BTreeSet::new());
+	}
+
+	#[rstest]
+	fn catalog_is_loaded_from_file() {
+		let path =
+			std::env::temp_dir().join(format!("mutilator-catalog-{}.json", std::process::id()));
+		std::fs::write(
+			&path,
+			r#"{"version": "test", "services": {"Valkey": {"plans": ["hobbyist"]}}}"#,
+		)
+		.unwrap();
+
+		let catalog = Catalog::load(Some(&path)).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(catalog.version, "test");
+		assert!(catalog.has_cloud("aws-eu-north-1"));
+		assert!(catalog.has_plan("Valkey", "hobbyist"));
+		assert!(!catalog.has_plan("Valkey", "startup-4"));
+		assert!(catalog.has_plan("PostgreSQL", "startup-4"));
+		assert_eq!(catalog.versions("Valkey"), Vec::<String>::new());
+	}
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// An IP network in CIDR notation, a bare address is treated as a single host
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+	address: IpAddr,
+	prefix: u8,
+}
+
+impl Cidr {
+	/// Whether `other` lies entirely within this network
+	pub fn contains(&self, other: &Cidr) -> bool {
+		if other.prefix < self.prefix {
+			return false;
+		}
+		match (self.address, other.address) {
+			(IpAddr::V4(a), IpAddr::V4(b)) => {
+				let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+				u32::from(a) & mask == u32::from(b) & mask
+			},
+			(IpAddr::V6(a), IpAddr::V6(b)) => {
+				let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+				u128::from(a) & mask == u128::from(b) & mask
+			},
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("{:?} is not a valid network", s);
+		let (address, prefix) = match s.trim().split_once('/') {
+			Some((address, prefix)) => (address, Some(prefix)),
+			None => (s.trim(), None),
+		};
+		let address: IpAddr = address.parse().map_err(|_| invalid())?;
+		let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+			None => max_prefix,
+		};
+		if prefix > max_prefix {
+			return Err(invalid());
+		}
+		Ok(Cidr { address, prefix })
+	}
+}
+
+impl TryFrom<String> for Cidr {
+	type Error = String;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl From<Cidr> for String {
+	fn from(cidr: Cidr) -> Self {
+		cidr.to_string()
+	}
+}
+
+impl fmt::Display for Cidr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.address, self.prefix)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use pretty_assertions::assert_eq;
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::same("10.0.0.0/8", "10.0.0.0/8", true)]
+	#[case::subnet("10.0.0.0/8", "10.1.2.0/24", true)]
+	#[case::host("10.0.0.0/8", "10.1.2.3", true)]
+	#[case::supernet("10.1.0.0/16", "10.0.0.0/8", false)]
+	#[case::outside("10.0.0.0/8", "11.0.0.0/8", false)]
+	#[case::everything("0.0.0.0/0", "192.168.0.1/32", true)]
+	#[case::ipv6("2001:db8::/32", "2001:db8:1::/48", true)]
+	#[case::mixed_families("0.0.0.0/0", "2001:db8::/32", false)]
+	fn contains(#[case] network: &str, #[case] other: &str, #[case] expected: bool) {
+		let network: Cidr = network.parse().unwrap();
+		let other: Cidr = other.parse().unwrap();
+
+		assert_eq!(network.contains(&other), expected);
+	}
+
+	#[rstest]
+	#[case::prefix_too_long("10.0.0.0/33")]
+	#[case::not_an_address("example.com/24")]
+	#[case::bad_prefix("10.0.0.0/x")]
+	fn invalid(#[case] value: &str) {
+		assert!(value.parse::<Cidr>().is_err());
+	}
+
+	#[rstest]
+	fn bare_address_is_host() {
+		let cidr: Cidr = "192.168.1.1".parse().unwrap();
+
+		assert_eq!(cidr.to_string(), "192.168.1.1/32");
+	}
+}
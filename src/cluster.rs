@@ -0,0 +1,112 @@
+use anyhow::Result;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{ApiResource, DynamicObject, GroupVersionKind, ObjectMeta};
+use kube::core::PartialObjectMeta;
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::{metadata_watcher, watcher, WatchStreamExt};
+use kube::{Api, Client, ResourceExt};
+use tracing::{info, instrument, warn};
+
+use crate::settings::AppConfig;
+
+pub type NamespaceStore = Store<PartialObjectMeta<Namespace>>;
+pub type ProjectVpcStore = Store<DynamicObject>;
+
+/// Cached views of cluster resources, kept up to date by reflectors
+#[derive(Clone)]
+pub struct ClusterState {
+	pub namespaces: NamespaceStore,
+	// Only watched when vpc.resolveRefs is enabled
+	pub project_vpcs: Option<ProjectVpcStore>,
+}
+
+impl ClusterState {
+	pub fn namespace(&self, name: &str) -> Option<ObjectMeta> {
+		self.namespaces
+			.get(&ObjectRef::new(name))
+			.map(|ns| ns.metadata.clone())
+	}
+}
+
+/// The Aiven operator's ProjectVPC resource
+pub fn project_vpc_resource() -> ApiResource {
+	ApiResource::from_gvk(&GroupVersionKind::gvk("aiven.io", "v1alpha1", "ProjectVPC"))
+}
+
+/// Look up a ProjectVPC in the store, returning its Aiven VPC ID once the operator has created it
+pub fn project_vpc_id(
+	store: &ProjectVpcStore,
+	namespace: &str,
+	name: &str,
+) -> Option<Option<String>> {
+	let obj_ref = ObjectRef::new_with(name, project_vpc_resource()).within(namespace);
+	store
+		.get(&obj_ref)
+		.map(|vpc| vpc.data["status"]["id"].as_str().map(|id| id.to_string()))
+}
+
+#[instrument(skip_all)]
+pub async fn start_reflectors(client: Client, config: &AppConfig) -> Result<ClusterState> {
+	let project_vpcs = if config.vpc.resolve_refs {
+		Some(start_project_vpc_reflector(client.clone()).await?)
+	} else {
+		None
+	};
+
+	let (namespaces, writer) = reflector::store();
+	let stream = metadata_watcher(Api::<Namespace>::all(client), watcher::Config::default())
+		.default_backoff()
+		.modify(|ns| {
+			ns.managed_fields_mut().clear();
+		})
+		.reflect(writer)
+		.applied_objects();
+	tokio::spawn(stream.for_each(|res| async move {
+		if let Err(err) = res {
+			warn!("Namespace watcher failed: {}", err);
+		}
+	}));
+
+	info!("Waiting for namespace cache to sync");
+	namespaces.wait_until_ready().await?;
+	info!(
+		"Namespace cache synced with {} namespaces",
+		namespaces.len()
+	);
+
+	Ok(ClusterState {
+		namespaces,
+		project_vpcs,
+	})
+}
+
+async fn start_project_vpc_reflector(client: Client) -> Result<ProjectVpcStore> {
+	let resource = project_vpc_resource();
+	let writer = reflector::store::Writer::new(resource.clone());
+	let project_vpcs = writer.as_reader();
+	let stream = watcher(
+		Api::<DynamicObject>::all_with(client, &resource),
+		watcher::Config::default(),
+	)
+	.default_backoff()
+	.modify(|vpc| {
+		vpc.managed_fields_mut().clear();
+	})
+	.reflect(writer)
+	.applied_objects();
+	tokio::spawn(stream.for_each(|res| async move {
+		if let Err(err) = res {
+			warn!("ProjectVPC watcher failed: {}", err);
+		}
+	}));
+
+	info!("Waiting for ProjectVPC cache to sync");
+	project_vpcs.wait_until_ready().await?;
+	info!(
+		"ProjectVPC cache synced with {} ProjectVPCs",
+		project_vpcs.len()
+	);
+
+	Ok(project_vpcs)
+}
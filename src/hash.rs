@@ -0,0 +1,6 @@
+/// FNV-1a hash, stable across releases and restarts
+pub fn stable_hash(key: &str) -> u64 {
+	key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+		(hash ^ byte as u64).wrapping_mul(0x100000001b3)
+	})
+}
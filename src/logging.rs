@@ -39,7 +39,7 @@ pub fn init_logging(config: &AppConfig) -> Result<()> {
 }
 
 fn init_otel(enable: bool) -> Result<Option<OpenTelemetryLayer<Registry, Tracer>>> {
-	if enable == false {
+	if !enable {
 		return Ok(None);
 	}
 
@@ -2,12 +2,19 @@ use anyhow::{anyhow, Result};
 use rustls::crypto;
 use tracing::info;
 
+use catalog::Catalog;
 use settings::AppConfig;
 
 mod aiven_object;
+mod catalog;
+mod cidr;
+mod cluster;
+mod hash;
 mod logging;
 mod mutators;
+mod pattern;
 mod settings;
+mod tag;
 mod web;
 
 fn main() -> Result<()> {
@@ -24,7 +31,13 @@ async fn app(config: AppConfig) -> Result<()> {
 	logging::init_logging(&config)?;
 	info!("Configuration loaded: {:?}", config);
 
-	web::start_web_server(config).await?;
+	let catalog = Catalog::load(config.catalog_path.as_deref())?;
+	info!("Catalog {} loaded", catalog.version);
+
+	let client = kube::Client::try_default().await?;
+	let cluster = cluster::start_reflectors(client, &config).await?;
+
+	web::start_web_server(config, cluster, catalog).await?;
 
 	Ok(())
 }
@@ -1,283 +1,3258 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use crate::aiven_object::AivenObject;
-use crate::settings::AppConfig;
+use crate::catalog::Catalog;
+use crate::cidr::Cidr;
+use crate::cluster::{self, ClusterState};
+use crate::hash::stable_hash;
+use crate::settings::{
+	for_kind_and_environment, AppConfig, AuthSecretConfig, BackupConfig, CloudConfig,
+	ConnInfoSecretConfig, CreatedByConfig, FieldAction, FieldsConfig, IpFilterConfig,
+	MaintenanceWindowConfig, MetadataConfig, MissingTeam, PlanConfig, PolicyAction, TagsConfig,
+	TeamConfig, UserConfigConfig, VersionConfig,
+};
+use crate::tag;
+use anyhow::{bail, Result};
 use json_patch::PatchOperation;
 use jsonptr::PointerBuf;
-use serde_json::{json, Value};
-use std::str::FromStr;
-use tracing::{debug, info, instrument};
+use kube::api::ObjectMeta;
+use kube::core::DynamicObject;
+use serde_json::{json, Map, Value};
+use tracing::{debug, info, instrument, warn};
 
+const BACKUP_TIME_KINDS: [&str; 2] = ["MySQL", "PostgreSQL"];
+const VERSION_FIELDS: [(&str, &str); 3] = [
+	("MySQL", "mysql_version"),
+	("OpenSearch", "opensearch_version"),
+	("PostgreSQL", "pg_version"),
+];
+pub const IP_FILTER_ANNOTATION: &str = "mutilator.nais.io/ip-filter";
+pub const AUTH_SECRET_ANNOTATION: &str = "mutilator.nais.io/auth-secret";
+pub const LOCATION_ANNOTATION: &str = "mutilator.nais.io/location";
+
+/// Cloud name an object should use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedCloud {
+	pub name: String,
+	// Chosen by namespace annotation or a per-namespace or per-kind override, so user cloud
+	// names may not differ
+	pub pinned: bool,
+}
+
+/// The Aiven cloud name for the object: a location selected by namespace annotation,
+/// a per-namespace or per-kind override, a configured cloud name, or the provider combined
+/// with the location
+pub fn resolve_cloud_name(
+	config: &AppConfig,
+	obj: &dyn AivenObject,
+	namespace: Option<&ObjectMeta>,
+) -> Result<ResolvedCloud> {
+	let annotated = namespace
+		.and_then(|ns| ns.annotations.as_ref())
+		.and_then(|annotations| annotations.get(LOCATION_ANNOTATION))
+		.filter(|location| !location.is_empty());
+	if let Some(location) = annotated {
+		if !config.cloud.allowed_locations.contains(location) {
+			bail!(
+				"namespace selects location {:?} which is not allowed, allowed locations: {}",
+				location,
+				config.cloud.allowed_locations.join(", ")
+			);
+		}
+		debug!("Using location {} from namespace annotation", location);
+		return Ok(ResolvedCloud {
+			name: format!("{}-{}", config.cloud.provider, location),
+			pinned: true,
+		});
+	}
+	let namespace = obj.get_namespace().unwrap_or_default();
+	let kind = obj.get_kind().unwrap_or_default();
+	let resolved = match config
+		.cloud
+		.namespaces
+		.get(&namespace)
+		.or(config.cloud.kinds.get(&kind))
+	{
+		Some(name) => ResolvedCloud {
+			name: name.clone(),
+			pinned: true,
+		},
+		None => ResolvedCloud {
+			name: config.default_cloud_name(),
+			pinned: false,
+		},
+	};
+	Ok(resolved)
+}
+
+/// Set cloudName, returning the cloud name the object ends up with. When locations are
+/// restricted, unchanged cloud names are kept on update, user cloud names differing from a
+/// pinned cloud are denied, and other user cloud names are kept within the allowed locations
+/// and denied outside them. When unrestricted, user cloud names are overwritten
 #[instrument(skip_all)]
 pub fn add_location(
-	location: String,
-	obj: &Box<dyn AivenObject>,
+	config: &CloudConfig,
+	cloud: ResolvedCloud,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
 	patches: &mut Vec<PatchOperation>,
-) {
-	let cloud_name = Value::String(format!("google-{}", location));
-	if obj.get_cloud_name().is_none() {
-		info!("Adding cloudName");
-		patches.push(add_patch(obj.cloud_name_path(), cloud_name));
-	} else {
-		info!("Overwriting cloudName");
-		patches.push(replace_patch("/spec/cloudName".into(), cloud_name));
+) -> Result<String> {
+	let cloud_name = cloud.name;
+	let allowed = config.allowed_cloud_names();
+	let old_cloud_name = old_obj.and_then(|old| old.get_cloud_name());
+	match obj.get_cloud_name() {
+		None => {
+			info!("Adding cloudName");
+			patches.push(add_patch(
+				obj.cloud_name_path(),
+				Value::String(cloud_name.clone()),
+			));
+		},
+		Some(existing) if existing == cloud_name => {},
+		Some(existing) if allowed.is_empty() => {
+			info!("Overwriting cloudName {}", existing);
+			patches.push(replace_patch(
+				obj.cloud_name_path(),
+				Value::String(cloud_name.clone()),
+			));
+		},
+		Some(existing) if old_cloud_name.as_ref() == Some(&existing) => {
+			info!("Keeping unchanged cloudName {}", existing);
+			return Ok(existing);
+		},
+		Some(existing) if cloud.pinned => bail!(
+			"cloudName {:?} is not allowed, this resource must use {}",
+			existing,
+			cloud_name
+		),
+		Some(existing) if allowed.contains(&existing) => {
+			info!("Keeping cloudName {}", existing);
+			return Ok(existing);
+		},
+		Some(existing) => bail!(
+			"cloudName {:?} is not allowed, use one of {}",
+			existing,
+			allowed.join(", ")
+		),
+	}
+	Ok(cloud_name)
+}
+
+/// Add the default plan when missing, returning the final plan
+#[instrument(skip_all)]
+pub fn add_plan(
+	config: &PlanConfig,
+	environment: &str,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+) -> Option<String> {
+	if let Some(plan) = obj.get_plan() {
+		return Some(plan);
+	}
+	let kind = obj.get_kind().unwrap_or_default();
+	match for_kind_and_environment(&config.defaults, &kind, environment) {
+		Some(plan) => {
+			info!("Adding plan {}", plan);
+			patches.push(add_patch(obj.plan_path(), Value::String(plan.clone())));
+			Some(plan.clone())
+		},
+		None => {
+			debug!("No default plan for {} in {}", kind, environment);
+			None
+		},
+	}
+}
+
+/// The userConfig key holding the service version, for kinds that have one
+pub fn version_field(kind: &str) -> Option<&'static str> {
+	VERSION_FIELDS
+		.iter()
+		.find(|(k, _)| *k == kind)
+		.map(|(_, field)| *field)
+}
+
+fn get_version(obj: &dyn AivenObject, field: &str) -> Option<String> {
+	match obj.get_user_config()?.get(field)? {
+		Value::String(version) => Some(version.clone()),
+		Value::Number(version) => Some(version.to_string()),
+		_ => None,
+	}
+}
+
+/// Pin the version of new services to the configured default, and keep the previous version
+/// on UPDATE. Services created without a version keep running Aiven's default, as adding one
+/// later would start a major version upgrade. Returns the final version
+#[instrument(skip_all)]
+pub fn add_version(
+	config: &VersionConfig,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
+	patches: &mut Vec<PatchOperation>,
+) -> Option<String> {
+	let kind = obj.get_kind().unwrap_or_default();
+	let field = version_field(&kind)?;
+	let user_config = obj.get_user_config().unwrap_or_default();
+	if user_config.contains_key(field) {
+		return get_version(obj, field);
+	}
+	let version = match old_obj {
+		Some(old_obj) => {
+			let version = get_version(old_obj, field)?;
+			info!("Keeping previous {} {}", field, version);
+			version
+		},
+		None => {
+			let Some(version) = config.defaults.get(&kind) else {
+				debug!("No default version for {}", kind);
+				return None;
+			};
+			info!("Adding {} {}", field, version);
+			version.clone()
+		},
+	};
+	add_user_config_value(obj, field, Value::String(version.clone()), patches);
+	Some(version)
+}
+
+/// Deny a final cloudName, plan or version missing from the catalog. Values unchanged since
+/// the previous object are kept, so retired plans and versions don't block updates
+#[instrument(skip_all)]
+pub fn validate_catalog(
+	catalog: &Catalog,
+	cloud_name: &str,
+	plan: Option<&str>,
+	version: Option<&str>,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
+) -> Result<()> {
+	let kind = obj.get_kind().unwrap_or_default();
+	let old_cloud_name = old_obj.and_then(|o| o.get_cloud_name());
+	if !catalog.has_cloud(cloud_name) && old_cloud_name.as_deref() != Some(cloud_name) {
+		bail!(
+			"cloudName {} does not exist in catalog {}",
+			cloud_name,
+			catalog.version
+		);
+	}
+	if let Some(plan) = plan {
+		let old_plan = old_obj.and_then(|o| o.get_plan());
+		if !catalog.has_plan(&kind, plan) && old_plan.as_deref() != Some(plan) {
+			bail!(
+				"plan {} does not exist for {} in catalog {}",
+				plan,
+				kind,
+				catalog.version
+			);
+		}
+	}
+	let versions = catalog.versions(&kind);
+	let Some(field) = version_field(&kind).filter(|_| !versions.is_empty()) else {
+		return Ok(());
+	};
+	if let Some(version) = version {
+		let old_version = old_obj.and_then(|o| get_version(o, field));
+		if !versions.iter().any(|v| v == version) && old_version.as_deref() != Some(version) {
+			bail!(
+				"{} {} does not exist for {}, use one of {}",
+				field,
+				version,
+				kind,
+				versions.join(", ")
+			);
+		}
+	}
+	Ok(())
+}
+
+/// Deny version changes on UPDATE that are not along the configured upgrade path
+#[instrument(skip_all)]
+pub fn validate_version_change(
+	config: &VersionConfig,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
+) -> Result<()> {
+	let kind = obj.get_kind().unwrap_or_default();
+	let (Some(field), Some(old_obj)) = (version_field(&kind), old_obj) else {
+		return Ok(());
+	};
+	let (Some(old), Some(new)) = (get_version(old_obj, field), get_version(obj, field)) else {
+		return Ok(());
+	};
+	if old == new {
+		return Ok(());
+	}
+	let Some(upgrades) = config.upgrades.get(&kind) else {
+		debug!(
+			"No upgrade path for {}, allowing {} {} -> {}",
+			kind, field, old, new
+		);
+		return Ok(());
+	};
+	let allowed = upgrades.get(&old).cloned().unwrap_or_default();
+	if !allowed.contains(&new) {
+		let allowed = match allowed.is_empty() {
+			true => "none".to_string(),
+			false => allowed.join(", "),
+		};
+		bail!(
+			"{} may not change from {} to {}, allowed next versions: {}",
+			field,
+			old,
+			new,
+			allowed
+		);
+	}
+	info!("Allowing {} upgrade {} -> {}", field, old, new);
+	Ok(())
+}
+
+#[instrument(skip_all)]
+pub fn resolve_team(
+	config: &TeamConfig,
+	obj: &dyn AivenObject,
+	namespace: Option<&ObjectMeta>,
+) -> Result<Option<String>> {
+	let from_label = namespace
+		.and_then(|ns| ns.labels.as_ref())
+		.and_then(|labels| labels.get(&config.label))
+		.filter(|team| !team.is_empty());
+	if let Some(team) = from_label {
+		debug!("Using team {} from namespace label {}", team, config.label);
+		return Ok(Some(team.clone()));
+	}
+	if let Some(team) = obj.get_namespace() {
+		debug!(
+			"Namespace has no {} label, using namespace name",
+			config.label
+		);
+		return Ok(Some(team));
+	}
+	match config.on_missing {
+		MissingTeam::Deny => bail!(
+			"unable to resolve team from namespace label {:?} or namespace name",
+			config.label
+		),
+		MissingTeam::Omit => {
+			warn!("Unable to resolve team, omitting team tag");
+			Ok(None)
+		},
 	}
 }
 
+/// Set the tags mutilator owns, returning their names
 #[instrument(skip_all)]
 pub fn add_tags(
 	config: &Arc<AppConfig>,
-	obj: &Box<dyn AivenObject>,
+	team: Option<String>,
+	namespace: Option<&ObjectMeta>,
+	computed: Vec<(String, String)>,
+	obj: &dyn AivenObject,
 	patches: &mut Vec<PatchOperation>,
-) {
-	let environment = config.tenant.environment.clone();
-	let tenant = config.tenant.name.clone();
-	let team = obj.get_team_name().unwrap();
-	if obj.get_tags().is_none() {
+	warnings: &mut Vec<String>,
+) -> Vec<String> {
+	let variables = template_variables(config, team.as_deref(), obj);
+	let mut enforced = vec![
+		("environment".to_string(), config.tenant.environment.clone()),
+		("tenant".to_string(), config.tenant.name.clone()),
+	];
+	if let Some(team) = team {
+		enforced.push(("team".to_string(), team));
+	}
+	let from_namespace = |entries: Option<&BTreeMap<String, String>>,
+	                      mapping: &BTreeMap<String, String>| {
+		mapping
+			.iter()
+			.filter_map(|(key, tag_name)| {
+				let value = entries?.get(key)?;
+				Some((tag_name.clone(), value.clone()))
+			})
+			.collect::<Vec<_>>()
+	};
+	let extra = computed
+		.into_iter()
+		.chain(render_entries(&config.tags.extra, &variables))
+		.chain(from_namespace(
+			namespace.and_then(|ns| ns.labels.as_ref()),
+			&config.tags.namespace_labels,
+		))
+		.chain(from_namespace(
+			namespace.and_then(|ns| ns.annotations.as_ref()),
+			&config.tags.namespace_annotations,
+		));
+	for (tag_name, tag_value) in extra {
+		if tag_value.is_empty() || enforced.iter().any(|(name, _)| *name == tag_name) {
+			continue;
+		}
+		enforced.push((tag_name, tag_value));
+	}
+	let enforced: Vec<(String, String)> = enforced
+		.into_iter()
+		.map(|(tag_name, tag_value)| (tag_name, tag::sanitize_value(&tag_value)))
+		.collect();
+	let owned = enforced.iter().map(|(name, _)| name.clone()).collect();
+
+	if let Some(tags) = obj.get_tags() {
+		for tag_name in tags.keys() {
+			let owned = enforced.iter().any(|(name, _)| name == tag_name);
+			if !owned && is_reserved_tag(config, tag_name) {
+				info!("Removing reserved tag {}", tag_name);
+				warnings.push(format!("tag {} is reserved and was removed", tag_name));
+				patches.push(remove_patch(obj.tag_path(tag_name)));
+			}
+		}
+		for (tag_name, tag_value) in enforced {
+			if tags
+				.get(&tag_name)
+				.is_some_and(|existing| *existing != tag_value)
+			{
+				warnings.push(format!(
+					"tag {} is reserved and was set to {:?}",
+					tag_name, tag_value
+				));
+			}
+			if let Some(patch) =
+				handle_entry("tag", &tags, &tag_name, tag_value, obj.tag_path(&tag_name))
+			{
+				patches.push(patch);
+			}
+		}
+	} else {
 		info!("Adding tags");
+		let tags: Map<String, Value> = enforced
+			.into_iter()
+			.map(|(tag_name, tag_value)| (tag_name, Value::String(tag_value)))
+			.collect();
+		patches.push(add_patch(obj.tags_path(), Value::Object(tags)));
+	}
+	owned
+}
+
+/// Deny user tags Aiven would reject, leaving out the tags add_tags set and reserved tags it
+/// removed
+#[instrument(skip_all)]
+pub fn validate_tags(config: &AppConfig, owned: &[String], obj: &dyn AivenObject) -> Result<()> {
+	let Some(tags) = obj.get_tags() else {
+		return Ok(());
+	};
+	let problems: Vec<String> = tags
+		.iter()
+		.filter(|(tag_name, _)| !owned.contains(tag_name) && !is_reserved_tag(config, tag_name))
+		.flat_map(|(tag_name, tag_value)| {
+			[
+				tag::check_key(tag_name),
+				tag::check_value(tag_name, tag_value),
+			]
+		})
+		.flatten()
+		.collect();
+	if !problems.is_empty() {
+		bail!("invalid tags: {}", problems.join(", "));
+	}
+	Ok(())
+}
+
+fn is_reserved_tag(config: &AppConfig, tag_name: &str) -> bool {
+	config
+		.tags
+		.reserved
+		.iter()
+		.any(|reserved| reserved == tag_name)
+		|| config
+			.tags
+			.reserved_prefixes
+			.iter()
+			.any(|prefix| tag_name.starts_with(prefix.as_str()))
+}
+
+#[instrument(skip_all)]
+pub fn normalize_tags(obj: &dyn AivenObject, patches: &mut Vec<PatchOperation>) -> Result<()> {
+	let Some(tags) = obj.get_raw_tags() else {
+		return Ok(());
+	};
+	for (tag_name, tag_value) in tags {
+		let normalized = match tag_value {
+			Value::String(_) => continue,
+			Value::Number(n) => n.to_string(),
+			Value::Bool(b) => b.to_string(),
+			Value::Null => {
+				info!("Removing {} tag with null value", tag_name);
+				patches.push(remove_patch(obj.tag_path(&tag_name)));
+				continue;
+			},
+			Value::Array(_) | Value::Object(_) => {
+				bail!(
+					"tag {:?} has a nested value, only strings are allowed as tag values",
+					tag_name
+				);
+			},
+		};
+		info!("Converting {} tag to string: {}", tag_name, normalized);
+		patches.push(replace_patch(
+			obj.tag_path(&tag_name),
+			Value::String(normalized),
+		));
+	}
+	Ok(())
+}
+
+fn handle_entry(
+	what: &str,
+	entries: &BTreeMap<String, String>,
+	name: &str,
+	value: String,
+	path: PointerBuf,
+) -> Option<PatchOperation> {
+	match entries.get(name) {
+		Some(existing) if existing.as_str() == value => {
+			debug!("{} {} already set to {}", name, what, existing);
+			None
+		},
+		Some(existing) => {
+			info!("Overwriting {} {}: {} => {}", name, what, existing, value);
+			Some(replace_patch(path, Value::String(value)))
+		},
+		None => {
+			info!("Adding {} {}: {}", name, what, value);
+			Some(add_patch(path, Value::String(value)))
+		},
+	}
+}
+
+/// Make sure the string map at `path` holds `entries`, creating it if missing
+fn ensure_entries(
+	what: &str,
+	existing: Option<BTreeMap<String, String>>,
+	path: PointerBuf,
+	entries: BTreeMap<String, String>,
+	patches: &mut Vec<PatchOperation>,
+) {
+	match existing {
+		Some(existing) => {
+			for (name, value) in entries {
+				let entry_path = path.with_trailing_token(&name);
+				if let Some(patch) = handle_entry(what, &existing, &name, value, entry_path) {
+					patches.push(patch);
+				}
+			}
+		},
+		None if entries.is_empty() => {},
+		None => {
+			info!("Adding {}s", what);
+			let entries = entries
+				.into_iter()
+				.map(|(name, value)| (name, Value::String(value)))
+				.collect();
+			patches.push(add_patch(path, Value::Object(entries)));
+		},
+	}
+}
+
+/// Values available to `{placeholder}` templates in config
+pub fn template_variables(
+	config: &AppConfig,
+	team: Option<&str>,
+	obj: &dyn AivenObject,
+) -> BTreeMap<&'static str, String> {
+	BTreeMap::from([
+		("kind", obj.get_kind().unwrap_or_default().to_lowercase()),
+		("name", obj.get_name().unwrap_or_default()),
+		("namespace", obj.get_namespace().unwrap_or_default()),
+		("team", team.unwrap_or_default().to_string()),
+		("tenant", config.tenant.name.clone()),
+		("environment", config.tenant.environment.clone()),
+	])
+}
+
+fn render_template(template: &str, variables: &BTreeMap<&'static str, String>) -> String {
+	variables
+		.iter()
+		.fold(template.to_string(), |rendered, (name, value)| {
+			rendered.replace(&format!("{{{}}}", name), value)
+		})
+}
+
+fn render_entries(
+	entries: &BTreeMap<String, String>,
+	variables: &BTreeMap<&'static str, String>,
+) -> BTreeMap<String, String> {
+	entries
+		.iter()
+		.map(|(name, value)| (name.clone(), render_template(value, variables)))
+		.collect()
+}
+
+#[instrument(skip_all)]
+pub fn add_metadata_labels(
+	config: &MetadataConfig,
+	variables: &BTreeMap<&'static str, String>,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+) {
+	let mut labels = render_entries(&config.labels, variables);
+	labels.retain(|_, value| !value.is_empty());
+	ensure_entries(
+		"label",
+		obj.get_labels(),
+		obj.labels_path(),
+		labels,
+		patches,
+	);
+}
+
+/// Who created the object: taken from the previous object on UPDATE so it never changes,
+/// and from the requesting user on CREATE
+pub fn resolve_created_by(
+	config: &CreatedByConfig,
+	username: Option<&str>,
+	old_obj: Option<&dyn AivenObject>,
+) -> Option<String> {
+	if let Some(old_obj) = old_obj {
+		return old_obj.get_annotations().get(&config.annotation).cloned();
+	}
+	let username = username.filter(|username| !username.is_empty())?;
+	let identity = config
+		.pattern
+		.as_ref()
+		.and_then(|pattern| pattern.extract(username))
+		.filter(|identity| !identity.is_empty())
+		.unwrap_or(username.to_string());
+	Some(identity)
+}
+
+/// Tags derived from object labels and annotations, eq. left by GitOps tooling
+pub fn source_tags(config: &TagsConfig, obj: &dyn AivenObject) -> Vec<(String, String)> {
+	let labels = obj.get_labels().unwrap_or_default();
+	let annotations = obj.get_annotations();
+	let mut tags: Vec<(String, String)> = Vec::new();
+	for rule in &config.sources {
+		if tags.iter().any(|(name, _)| *name == rule.tag) {
+			continue;
+		}
+		let source = match (&rule.label, &rule.annotation) {
+			(Some(label), _) => labels.get(label),
+			(None, Some(annotation)) => annotations.get(annotation),
+			(None, None) => None,
+		};
+		let Some(source) = source else {
+			continue;
+		};
+		let value = match &rule.pattern {
+			Some(pattern) => pattern.extract(source),
+			None => Some(source.clone()),
+		};
+		let value = value.map(|value| rule.value.clone().unwrap_or(value));
+		if let Some(value) = value.filter(|value| !value.is_empty()) {
+			debug!("Using {} tag {} from object metadata", rule.tag, value);
+			tags.push((rule.tag.clone(), value));
+		}
+	}
+	tags
+}
+
+#[instrument(skip_all)]
+pub fn add_created_by_annotation(
+	config: &CreatedByConfig,
+	created_by: Option<&str>,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+) {
+	let Some(created_by) = created_by else {
+		return;
+	};
+	ensure_entries(
+		"annotation",
+		Some(obj.get_annotations()).filter(|annotations| !annotations.is_empty()),
+		obj.annotations_path(),
+		BTreeMap::from([(config.annotation.clone(), created_by.to_string())]),
+		patches,
+	);
+}
+
+/// Ensure connInfoSecretTarget has a name and the configured labels and annotations. The name
+/// template only applies on CREATE, on UPDATE the secret keeps its previous name, which is the
+/// resource name when it was never set
+#[instrument(skip_all)]
+pub fn add_conn_info_secret_target(
+	config: &ConnInfoSecretConfig,
+	variables: &BTreeMap<&'static str, String>,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
+	patches: &mut Vec<PatchOperation>,
+) {
+	let name = match old_obj {
+		None => render_template(&config.name_template, variables),
+		Some(old_obj) => old_obj
+			.get_conn_info_secret_target()
+			.and_then(|target| target.get("name")?.as_str().map(str::to_string))
+			.unwrap_or_else(|| obj.get_name().unwrap_or_default()),
+	};
+	let labels = render_entries(&config.labels, variables);
+	let annotations = render_entries(&config.annotations, variables);
+	let path = obj.conn_info_secret_target_path();
+
+	let Some(target) = obj.get_conn_info_secret_target() else {
+		info!("Adding connInfoSecretTarget {}", name);
+		let mut target = Map::new();
+		target.insert("name".to_string(), Value::String(name));
+		for (key, entries) in [("labels", labels), ("annotations", annotations)] {
+			if !entries.is_empty() {
+				let entries = entries
+					.into_iter()
+					.map(|(name, value)| (name, Value::String(value)))
+					.collect();
+				target.insert(key.to_string(), Value::Object(entries));
+			}
+		}
+		patches.push(add_patch(path, Value::Object(target)));
+		return;
+	};
+
+	if !target.contains_key("name") {
+		info!("Adding connInfoSecretTarget name {}", name);
 		patches.push(add_patch(
-			obj.tags_path(),
-			json!({
-				"environment": environment,
-				"tenant": tenant,
-				"team": team
-			}),
+			path.with_trailing_token("name"),
+			Value::String(name),
 		));
-	} else {
-		let tags = obj.get_tags().unwrap();
-		for (tag_name, tag_value) in [
-			("environment", environment.clone()),
-			("tenant", tenant.clone()),
-			("team", team.clone()),
-		] {
-			if let Some(patch) = handle_tag(&tags, tag_name, tag_value, obj.tag_path(tag_name)) {
-				patches.push(patch);
+	}
+	ensure_entries(
+		"secret label",
+		string_map(target.get("labels")),
+		path.with_trailing_token("labels"),
+		labels,
+		patches,
+	);
+	ensure_entries(
+		"secret annotation",
+		string_map(target.get("annotations")),
+		path.with_trailing_token("annotations"),
+		annotations,
+		patches,
+	);
+}
+
+fn string_map(value: Option<&Value>) -> Option<BTreeMap<String, String>> {
+	value.and_then(Value::as_object).map(|entries| {
+		entries
+			.iter()
+			.filter_map(|(name, value)| value.as_str().map(|v| (name.clone(), v.to_string())))
+			.collect()
+	})
+}
+
+/// Point authSecretRef at the token secret for the namespace, and deny references to other secrets
+#[instrument(skip_all)]
+pub fn add_auth_secret_ref(
+	config: &AuthSecretConfig,
+	obj: &dyn AivenObject,
+	namespace: Option<&ObjectMeta>,
+	patches: &mut Vec<PatchOperation>,
+) -> Result<()> {
+	let name = namespace
+		.and_then(|ns| ns.annotations.as_ref())
+		.and_then(|annotations| annotations.get(AUTH_SECRET_ANNOTATION))
+		.filter(|name| !name.is_empty())
+		.unwrap_or(&config.name);
+
+	let Some(auth_secret_ref) = obj.get_auth_secret_ref() else {
+		info!("Adding authSecretRef to secret {}", name);
+		patches.push(add_patch(
+			obj.auth_secret_ref_path(),
+			json!({"name": name, "key": config.key}),
+		));
+		return Ok(());
+	};
+
+	match auth_secret_ref.get("name").and_then(Value::as_str) {
+		Some(existing) if existing == name || config.allowed.iter().any(|a| a == existing) => {
+			debug!("Keeping authSecretRef to secret {}", existing);
+		},
+		Some(existing) => bail!(
+			"authSecretRef may not point at secret {:?}, use {:?} or leave it out",
+			existing,
+			name
+		),
+		None => {
+			info!("Adding authSecretRef name {}", name);
+			patches.push(add_patch(
+				obj.auth_secret_ref_path().with_trailing_token("name"),
+				Value::String(name.clone()),
+			));
+		},
+	}
+	if !auth_secret_ref.contains_key("key") {
+		info!("Adding authSecretRef key {}", config.key);
+		patches.push(add_patch(
+			obj.auth_secret_ref_path().with_trailing_token("key"),
+			Value::String(config.key.clone()),
+		));
+	}
+	Ok(())
+}
+
+/// Strip or deny fields that go against platform policy. Returns the object with the fields
+/// removed, so later mutators patch what will be left rather than what the user sent
+#[instrument(skip_all)]
+pub fn remove_forbidden_fields<'a>(
+	config: &FieldsConfig,
+	obj: &'a DynamicObject,
+	patches: &mut Vec<PatchOperation>,
+) -> Result<Cow<'a, DynamicObject>> {
+	let kind = obj.get_kind().unwrap_or_default();
+	let rules = [kind.as_str(), "*"]
+		.into_iter()
+		.filter_map(|k| config.forbidden.get(k))
+		.flatten();
+	let mut stripped = Cow::Borrowed(obj);
+	for rule in rules {
+		// Checked against the stripped object, so a pointer listed twice or below a removed
+		// field is only removed once
+		if stripped.get_pointer(&rule.pointer).is_none() {
+			continue;
+		}
+		match rule.action {
+			FieldAction::Remove => {
+				info!("Removing forbidden field {}", rule.pointer);
+				rule.pointer.delete(&mut stripped.to_mut().data);
+				patches.push(remove_patch(rule.pointer.clone()));
+			},
+			FieldAction::Deny => match &rule.reason {
+				Some(reason) => bail!("{} is not allowed: {}", rule.pointer, reason),
+				None => bail!("{} is not allowed", rule.pointer),
+			},
+		}
+	}
+	Ok(stripped)
+}
+
+#[instrument(skip_all)]
+pub fn add_termination_protection(obj: &dyn AivenObject, patches: &mut Vec<PatchOperation>) {
+	if obj.get_termination_protection().is_none() {
+		info!("Enabling terminationProtection");
+		patches.push(add_patch(
+			obj.termination_protection_path(),
+			Value::Bool(true),
+		));
+	}
+}
+
+/// Check a projectVPCRef, returning whether the object uses one. Refs are resolved against the
+/// ProjectVPC cache when it is enabled, denying refs to VPCs outside the tenant or without an ID
+#[instrument(skip_all)]
+pub fn check_project_vpc_ref(
+	config: &AppConfig,
+	cluster: &ClusterState,
+	obj: &dyn AivenObject,
+) -> Result<bool> {
+	let Some(vpc_ref) = obj.get_project_vpc_ref() else {
+		return Ok(false);
+	};
+	if obj.get_project_vpc_id().is_some() {
+		bail!("only one of projectVpcId and projectVPCRef may be set");
+	}
+	let Some(name) = vpc_ref.get("name").and_then(Value::as_str) else {
+		bail!("projectVPCRef must have a name");
+	};
+	let namespace = match vpc_ref.get("namespace").and_then(Value::as_str) {
+		Some(namespace) => namespace.to_string(),
+		None => obj.get_namespace().unwrap_or_default(),
+	};
+	let Some(project_vpcs) = &cluster.project_vpcs else {
+		debug!("Not resolving projectVPCRef {}/{}", namespace, name);
+		return Ok(true);
+	};
+	match cluster::project_vpc_id(project_vpcs, &namespace, name) {
+		None => bail!("ProjectVPC {}/{} does not exist", namespace, name),
+		Some(Some(id)) if !config.is_tenant_project_vpc(&id) => bail!(
+			"ProjectVPC {}/{} is not a project VPC of this tenant",
+			namespace,
+			name
+		),
+		Some(Some(id)) => info!("projectVPCRef {}/{} resolved to {}", namespace, name, id),
+		Some(None) => bail!(
+			"ProjectVPC {}/{} has no ID yet, try again once it is ready",
+			namespace,
+			name
+		),
+	}
+	Ok(true)
+}
+
+/// Deny a projectVpcId set by the user unless it is a configured VPC of the tenant. ProjectVPCs
+/// in the cluster are not enough, as they may belong to other tenants. Unchanged IDs are kept
+#[instrument(skip_all)]
+pub fn validate_project_vpc_id(
+	config: &AppConfig,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
+) -> Result<()> {
+	let Some(id) = obj.get_project_vpc_id() else {
+		return Ok(());
+	};
+	if config.is_tenant_project_vpc(&id) {
+		return Ok(());
+	}
+	if old_obj.and_then(|o| o.get_project_vpc_id()).as_deref() == Some(id.as_str()) {
+		debug!("Keeping unchanged projectVpcId {}", id);
+		return Ok(());
+	}
+	bail!("projectVpcId {} is not a project VPC of this tenant", id)
+}
+
+/// Set projectVpcId to the VPC of the object's cloud, denying when the cloud has none
+#[instrument(skip_all)]
+pub fn add_project_vpc_id(
+	config: &AppConfig,
+	cloud_name: &str,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+) -> Result<()> {
+	if obj.get_project_vpc_id().is_some() {
+		return Ok(());
+	}
+	let Some(project_vpc_id) = config.project_vpc_id_for(cloud_name) else {
+		bail!("no project VPC is configured for cloud {}", cloud_name);
+	};
+	info!("Adding projectVpcId for {}", cloud_name);
+	patches.push(add_patch(
+		obj.project_vpc_id_path(),
+		Value::String(project_vpc_id.to_string()),
+	));
+	Ok(())
+}
+
+/// Spread maintenance windows over the configured days and hours, keeping
+/// whatever was assigned earlier so a window never moves once set
+#[instrument(skip_all)]
+pub fn add_maintenance_window(
+	config: &MaintenanceWindowConfig,
+	obj: &dyn AivenObject,
+	old_obj: Option<&dyn AivenObject>,
+	patches: &mut Vec<PatchOperation>,
+) {
+	if config.days.is_empty() || config.hours.is_empty() {
+		debug!("No maintenance window days or hours configured");
+		return;
+	}
+	let hash = stable_hash(&object_key(obj));
+	let days = config.days.len() as u64;
+	let hours = config.hours.len() as u64;
+
+	if obj.get_maintenance_window_dow().is_none() {
+		let dow = match old_obj.and_then(|o| o.get_maintenance_window_dow()) {
+			Some(dow) => {
+				info!("Keeping previous maintenanceWindowDow {}", dow);
+				dow
+			},
+			None => {
+				let dow = config.days[(hash % days) as usize].to_string();
+				info!("Adding maintenanceWindowDow {}", dow);
+				dow
+			},
+		};
+		patches.push(add_patch(
+			obj.maintenance_window_dow_path(),
+			Value::String(dow),
+		));
+	}
+	if obj.get_maintenance_window_time().is_none() {
+		let time = match old_obj.and_then(|o| o.get_maintenance_window_time()) {
+			Some(time) => {
+				info!("Keeping previous maintenanceWindowTime {}", time);
+				time
+			},
+			None => {
+				let hour = config.hours[((hash / days) % hours) as usize];
+				let time = format!("{:02}:00:00", hour);
+				info!("Adding maintenanceWindowTime {}", time);
+				time
+			},
+		};
+		patches.push(add_patch(
+			obj.maintenance_window_time_path(),
+			Value::String(time),
+		));
+	}
+}
+
+/// Spread backups over the configured time ranges for kinds supporting
+/// `backup_hour`/`backup_minute`, leaving values set by the user alone
+#[instrument(skip_all)]
+pub fn add_backup_time(
+	config: &BackupConfig,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+) {
+	let kind = obj.get_kind().unwrap_or_default();
+	if !BACKUP_TIME_KINDS.contains(&kind.as_str()) {
+		debug!("{} does not support backup times", kind);
+		return;
+	}
+	let total: u64 = config
+		.time_ranges
+		.iter()
+		.map(|range| range.minutes() as u64)
+		.sum();
+	if total == 0 {
+		debug!("No backup time ranges configured");
+		return;
+	}
+
+	let mut offset = stable_hash(&format!("{}/backup", object_key(obj))) % total;
+	let mut minute_of_day = 0;
+	for range in &config.time_ranges {
+		let minutes = range.minutes() as u64;
+		if offset < minutes {
+			minute_of_day = (range.start as u64 + offset) % (24 * 60);
+			break;
+		}
+		offset -= minutes;
+	}
+
+	let user_config = obj.get_user_config().unwrap_or_default();
+	for (key, value) in [
+		("backup_hour", minute_of_day / 60),
+		("backup_minute", minute_of_day % 60),
+	] {
+		if user_config.contains_key(key) {
+			debug!("{} already set", key);
+			continue;
+		}
+		info!("Adding {} {}", key, value);
+		add_user_config_value(obj, key, Value::from(value), patches);
+	}
+}
+
+/// Inject networks from named sets into `userConfig.ip_filter`, and check that
+/// existing entries lie within the configured sets
+#[instrument(skip_all)]
+pub fn add_ip_filter(
+	config: &IpFilterConfig,
+	environment: &str,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+	warnings: &mut Vec<String>,
+) -> Result<()> {
+	let annotated: Option<Vec<String>> =
+		obj.get_annotations().get(IP_FILTER_ANNOTATION).map(|sets| {
+			sets.split(',')
+				.map(str::trim)
+				.filter(|name| !name.is_empty())
+				.map(String::from)
+				.collect()
+		});
+	let set_names = match &annotated {
+		Some(set_names) => set_names.clone(),
+		None => {
+			let kind = obj.get_kind().unwrap_or_default();
+			for_kind_and_environment(&config.defaults, &kind, environment)
+				.cloned()
+				.unwrap_or_default()
+		},
+	};
+
+	let mut entries: Vec<(Cidr, &str)> = Vec::new();
+	for set_name in &set_names {
+		let Some(set) = config.sets.get(set_name) else {
+			bail!(
+				"unknown network set {:?} in {} annotation",
+				set_name,
+				IP_FILTER_ANNOTATION
+			);
+		};
+		for network in set {
+			if !entries.iter().any(|(existing, _)| existing == network) {
+				entries.push((*network, set_name));
+			}
+		}
+	}
+
+	let Some(existing) = obj.get_ip_filter() else {
+		if !entries.is_empty() {
+			info!("Adding ip_filter from network sets {:?}", set_names);
+			let value = entries
+				.into_iter()
+				.map(|(network, set_name)| ip_filter_entry(network, set_name))
+				.collect();
+			add_user_config_value(obj, "ip_filter", Value::Array(value), patches);
+		}
+		return Ok(());
+	};
+
+	let existing: Vec<Option<Cidr>> = existing
+		.iter()
+		.map(|entry| {
+			entry
+				.as_str()
+				.or_else(|| entry["network"].as_str())
+				.and_then(|network| network.parse().ok())
+		})
+		.collect();
+	if !config.sets.is_empty() {
+		for (index, network) in existing.iter().enumerate() {
+			let allowed = network.is_some_and(|network| {
+				config
+					.sets
+					.values()
+					.flatten()
+					.any(|allowed| allowed.contains(&network))
+			});
+			if allowed {
+				continue;
+			}
+			let message = match network {
+				Some(network) => format!(
+					"ip_filter entry {} is outside the allowed network sets",
+					network
+				),
+				None => format!("ip_filter entry {} is not a valid network", index),
+			};
+			match config.policy {
+				PolicyAction::Deny => bail!(message),
+				PolicyAction::Warn => {
+					warn!("{}", message);
+					warnings.push(message);
+				},
 			}
 		}
 	}
-}
 
-fn handle_tag(
-	tags: &BTreeMap<String, String>,
-	tag_name: &str,
-	tag_value: String,
-	tag_path: String,
-) -> Option<PatchOperation> {
-	match tags.get(tag_name) {
-		Some(value) if value.as_str() == tag_value => {
-			debug!("{} tag already set to {}", tag_name, value);
-			None
-		},
-		Some(value) => {
-			info!("Overwriting {} tag: {} => {}", tag_name, value, tag_value);
-			Some(replace_patch(tag_path, Value::String(tag_value)))
-		},
-		None => {
-			info!("Adding {} tag: {}", tag_name, tag_value);
-			Some(add_patch(tag_path, Value::String(tag_value)))
-		},
+	if annotated.is_some() {
+		for (network, set_name) in entries {
+			if existing.contains(&Some(network)) {
+				continue;
+			}
+			info!(
+				"Appending {} from network set {} to ip_filter",
+				network, set_name
+			);
+			patches.push(add_patch(
+				obj.ip_filter_path().with_trailing_token("-"),
+				ip_filter_entry(network, set_name),
+			));
+		}
+	}
+	Ok(())
+}
+
+/// Deep-merge the userConfig defaults for the kind, only adding keys the user didn't set
+#[instrument(skip_all)]
+pub fn merge_user_config_defaults(
+	config: &UserConfigConfig,
+	obj: &dyn AivenObject,
+	patches: &mut Vec<PatchOperation>,
+) {
+	let kind = obj.get_kind().unwrap_or_default();
+	let Some(defaults) = config.defaults.get(&kind) else {
+		return;
+	};
+	let user_config = obj.get_user_config().unwrap_or_default();
+	for (key, value) in defaults {
+		let path = obj.user_config_value_path(key);
+		let added = patches
+			.iter()
+			.any(|p| matches!(p, PatchOperation::Add(add) if add.path == path));
+		if added {
+			continue;
+		}
+		match (user_config.get(key), value) {
+			(None, _) => {
+				info!("Adding userConfig default {}", key);
+				add_user_config_value(obj, key, value.clone(), patches);
+			},
+			(Some(Value::Object(existing)), Value::Object(defaults)) => {
+				merge_entries(existing, defaults, path, patches);
+			},
+			_ => debug!("Keeping userConfig value {}", key),
+		}
+	}
+}
+
+fn merge_entries(
+	existing: &Map<String, Value>,
+	defaults: &Map<String, Value>,
+	path: PointerBuf,
+	patches: &mut Vec<PatchOperation>,
+) {
+	for (key, value) in defaults {
+		let path = path.with_trailing_token(key);
+		match (existing.get(key), value) {
+			(None, _) => {
+				info!("Adding userConfig default {}", path);
+				patches.push(add_patch(path, value.clone()));
+			},
+			(Some(Value::Object(existing)), Value::Object(defaults)) => {
+				merge_entries(existing, defaults, path, patches);
+			},
+			_ => {},
+		}
+	}
+}
+
+fn ip_filter_entry(network: Cidr, set_name: &str) -> Value {
+	let mut entry = Map::new();
+	entry.insert("network".to_string(), Value::String(network.to_string()));
+	entry.insert(
+		"description".to_string(),
+		Value::String(set_name.to_string()),
+	);
+	Value::Object(entry)
+}
+
+/// Add a key to `spec.userConfig`, creating the object first if needed
+fn add_user_config_value(
+	obj: &dyn AivenObject,
+	key: &str,
+	value: Value,
+	patches: &mut Vec<PatchOperation>,
+) {
+	let user_config_path = obj.user_config_path();
+	let created = patches
+		.iter()
+		.any(|p| matches!(p, PatchOperation::Add(add) if add.path == user_config_path));
+	if obj.get_user_config().is_none() && !created {
+		patches.push(add_patch(user_config_path, Value::Object(Map::new())));
+	}
+	patches.push(add_patch(obj.user_config_value_path(key), value));
+}
+
+fn object_key(obj: &dyn AivenObject) -> String {
+	format!(
+		"{}/{}",
+		obj.get_namespace().unwrap_or_default(),
+		obj.get_name().unwrap_or_default()
+	)
+}
+
+fn add_patch(path: PointerBuf, value: Value) -> PatchOperation {
+	PatchOperation::Add(json_patch::AddOperation { path, value })
+}
+
+fn replace_patch(path: PointerBuf, value: Value) -> PatchOperation {
+	PatchOperation::Replace(json_patch::ReplaceOperation { path, value })
+}
+
+fn remove_patch(path: PointerBuf) -> PatchOperation {
+	PatchOperation::Remove(json_patch::RemoveOperation { path })
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::{BTreeMap, BTreeSet};
+
+	use kube::core::DynamicObject;
+	use kube::runtime::{reflector, watcher};
+	use pretty_assertions::assert_eq;
+	use rstest::*;
+	use serde_json::json;
+
+	use crate::settings::{ForbiddenField, LogLevel, SourceTagRule, Tenant, WebConfig, Weekday};
+
+	use super::*;
+
+	const ENVIRONMENT: &str = "test-tenant-env";
+	const TENANT: &str = "test-tenant-name";
+	const NAMESPACE: &str = "test-namespace";
+	const PROJECT_VPC_ID: &str = "test-vpc-id";
+	const LOCATION: &str = "test-location";
+
+	const TAG_PAIRS: [(&str, &str); 3] = [
+		("environment", ENVIRONMENT),
+		("tenant", TENANT),
+		("team", NAMESPACE),
+	];
+
+	#[fixture]
+	pub fn config() -> Arc<AppConfig> {
+		Arc::new(AppConfig {
+			log_format: Default::default(),
+			log_level: LogLevel::Trace,
+			web: WebConfig {
+				bind_address: "".to_string(),
+				certificate_path: None,
+				private_key_path: None,
+			},
+			tenant: Tenant {
+				environment: ENVIRONMENT.to_string(),
+				name: TENANT.to_string(),
+			},
+			team: Default::default(),
+			auth_secret: Default::default(),
+			maintenance_window: Default::default(),
+			backup: Default::default(),
+			ip_filter: Default::default(),
+			plan: Default::default(),
+			version: Default::default(),
+			fields: Default::default(),
+			user_config: Default::default(),
+			tags: Default::default(),
+			created_by: Default::default(),
+			metadata: Default::default(),
+			conn_info_secret: Default::default(),
+			project_vpc_id: PROJECT_VPC_ID.to_string(),
+			vpc: Default::default(),
+			location: LOCATION.to_string(),
+			cloud: Default::default(),
+			catalog_path: None,
+			otel_enabled: false,
+		})
+	}
+
+	#[rstest]
+	fn add_tags_when_no_tags_before(config: Arc<AppConfig>) {
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		add_tags(
+			&config,
+			Some(NAMESPACE.to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+
+		assert_eq!(patches.len(), 1);
+		let patch = patches.pop().unwrap();
+		match patch {
+			PatchOperation::Add(add) => {
+				assert_eq!(add.path, "/spec/tags");
+				assert_eq!(
+					add.value,
+					json!({
+						"environment": ENVIRONMENT.to_string(),
+						"team": NAMESPACE.to_string(),
+						"tenant": TENANT.to_string(),
+					}),
+					"incorrect json structure added"
+				);
+			},
+			_ => {
+				panic!("Wrong patch operation in patches");
+			},
+		}
+	}
+
+	#[rstest]
+	fn add_tags_when_other_tags_exists(config: Arc<AppConfig>) {
+		let valkey = create_object(Some(BTreeMap::from([(
+			"cool".to_string(),
+			"tag".to_string(),
+		)])));
+		let mut patches: Vec<PatchOperation> = Vec::new();
+
+		add_tags(
+			&config,
+			Some(NAMESPACE.to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+		let actual = make_comparable_set(&patches);
+
+		let mut expected = BTreeSet::new();
+		for (key, value) in TAG_PAIRS {
+			expected.insert(("add", format!("/spec/tags/{}", key), value.to_string()));
+		}
+		assert_eq!(actual, expected, "contains expected patches");
+	}
+
+	#[rstest]
+	fn replace_tags_when_wrong_values_are_set(config: Arc<AppConfig>) {
+		let mut existing_tags = BTreeMap::new();
+		for (key, _value) in TAG_PAIRS {
+			existing_tags.insert(key.to_string(), "invalid".to_string());
+		}
+		let valkey = create_object(Some(existing_tags));
+		let mut patches: Vec<PatchOperation> = Vec::new();
+
+		add_tags(
+			&config,
+			Some(NAMESPACE.to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+		let actual = make_comparable_set(&patches);
+
+		let mut expected = BTreeSet::new();
+		for (key, value) in TAG_PAIRS {
+			expected.insert(("replace", format!("/spec/tags/{}", key), value.to_string()));
+		}
+		assert_eq!(actual, expected, "contains expected patches");
+	}
+
+	#[rstest]
+	#[case(ENVIRONMENT, TENANT)]
+	#[case(ENVIRONMENT, NAMESPACE)]
+	#[case(TENANT, NAMESPACE)]
+	#[case(TENANT, ENVIRONMENT)]
+	#[case(NAMESPACE, ENVIRONMENT)]
+	#[case(NAMESPACE, TENANT)]
+	fn add_or_replace_as_needed(
+		config: Arc<AppConfig>,
+		#[case] correct: &str,
+		#[case] invalid: &str,
+	) {
+		let mut existing_tags = BTreeMap::new();
+		for (key, value) in TAG_PAIRS {
+			match value {
+				c if c == correct => {
+					existing_tags.insert(key.to_string(), correct.to_string());
+				},
+				i if i == invalid => {
+					existing_tags.insert(key.to_string(), "invalid".to_string());
+				},
+				_ => {},
+			}
+		}
+		let valkey = create_object(Some(existing_tags));
+		let mut patches: Vec<PatchOperation> = Vec::new();
+
+		add_tags(
+			&config,
+			Some(NAMESPACE.to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+		let actual = make_comparable_set(&patches);
+
+		let mut expected = BTreeSet::new();
+		for (key, value) in TAG_PAIRS {
+			match value {
+				v if v == correct => {},
+				v if v == invalid => {
+					expected.insert(("replace", format!("/spec/tags/{}", key), value.to_string()));
+				},
+				_ => {
+					expected.insert(("add", format!("/spec/tags/{}", key), value.to_string()));
+				},
+			}
+		}
+		assert_eq!(actual, expected, "contains expected patches");
+	}
+
+	#[rstest]
+	#[case::slash("cost/center", "/spec/tags/cost~1center")]
+	#[case::tilde("~owner", "/spec/tags/~0owner")]
+	fn handle_tag_with_special_characters(#[case] tag_name: &str, #[case] expected_path: &str) {
+		let valkey = create_object(Some(BTreeMap::from([(
+			tag_name.to_string(),
+			"invalid".to_string(),
+		)])));
+		let tags = valkey.get_tags().unwrap();
+
+		let replace = handle_entry(
+			"tag",
+			&tags,
+			tag_name,
+			"value".to_string(),
+			valkey.tag_path(tag_name),
+		);
+		let add = handle_entry(
+			"tag",
+			&BTreeMap::new(),
+			tag_name,
+			"value".to_string(),
+			valkey.tag_path(tag_name),
+		);
+
+		let patches = [replace.unwrap(), add.unwrap()];
+		let actual = make_comparable_set(&patches);
+		let expected = BTreeSet::from([
+			("add", expected_path.to_string(), "value".to_string()),
+			("replace", expected_path.to_string(), "value".to_string()),
+		]);
+		assert_eq!(actual, expected, "contains expected patches");
+	}
+
+	#[rstest]
+	fn normalize_tags_converts_scalars_and_removes_nulls() {
+		let valkey = create_object_with_tags(json!({
+			"string": "value",
+			"number": 123,
+			"bool": false,
+			"null": null
+		}));
+		let mut patches: Vec<PatchOperation> = Vec::new();
+
+		normalize_tags(&valkey, &mut patches).unwrap();
+
+		assert_eq!(
+			patches,
+			vec![
+				replace_patch(valkey.tag_path("bool"), json!("false")),
+				remove_patch(valkey.tag_path("null")),
+				replace_patch(valkey.tag_path("number"), json!("123")),
+			]
+		);
+	}
+
+	#[rstest]
+	#[case::object(json!({"key": "value"}))]
+	#[case::array(json!(["value"]))]
+	fn normalize_tags_denies_nested_values(#[case] value: Value) {
+		let valkey = create_object_with_tags(json!({ "team": "value", "cost/center": value }));
+		let mut patches: Vec<PatchOperation> = Vec::new();
+
+		let err = normalize_tags(&valkey, &mut patches).unwrap_err();
+
+		assert!(
+			err.to_string().contains("\"cost/center\""),
+			"error should name the offending tag: {}",
+			err
+		);
+	}
+
+	#[rstest]
+	fn null_enforced_tag_is_removed_then_added(config: Arc<AppConfig>) {
+		let valkey = create_object_with_tags(json!({
+			"environment": ENVIRONMENT,
+			"tenant": TENANT,
+			"team": null
+		}));
+		let mut patches: Vec<PatchOperation> = Vec::new();
+
+		normalize_tags(&valkey, &mut patches).unwrap();
+		add_tags(
+			&config,
+			Some(NAMESPACE.to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+
+		assert_eq!(
+			patches,
+			vec![
+				remove_patch(valkey.tag_path("team")),
+				add_patch(valkey.tag_path("team"), json!(NAMESPACE)),
+			]
+		);
+	}
+
+	#[rstest]
+	#[case::label("team", Some("from-label"), Some(NAMESPACE), Some("from-label"))]
+	#[case::other_label("nais.io/team", Some("from-label"), Some(NAMESPACE), Some(NAMESPACE))]
+	#[case::empty_label("team", Some(""), Some(NAMESPACE), Some(NAMESPACE))]
+	#[case::no_label("team", None, Some(NAMESPACE), Some(NAMESPACE))]
+	#[case::nothing("team", None, None, None)]
+	fn resolve_team_from_label_or_namespace(
+		#[case] label: &str,
+		#[case] label_value: Option<&str>,
+		#[case] namespace: Option<&str>,
+		#[case] expected: Option<&str>,
+	) {
+		let config = TeamConfig {
+			label: label.to_string(),
+			on_missing: MissingTeam::Omit,
+		};
+		let mut valkey = create_object(None);
+		valkey.metadata.namespace = namespace.map(|ns| ns.to_string());
+		let namespace_meta = ObjectMeta {
+			name: namespace.map(|ns| ns.to_string()),
+			labels: label_value.map(|v| BTreeMap::from([("team".to_string(), v.to_string())])),
+			..Default::default()
+		};
+
+		let team = resolve_team(&config, &valkey, Some(&namespace_meta)).unwrap();
+
+		assert_eq!(team.as_deref(), expected);
+	}
+
+	#[rstest]
+	fn resolve_team_denies_when_missing() {
+		let config = TeamConfig {
+			label: "team".to_string(),
+			on_missing: MissingTeam::Deny,
+		};
+		let mut valkey = create_object(None);
+		valkey.metadata.namespace = None;
+
+		assert!(resolve_team(&config, &valkey, None).is_err());
+	}
+
+	#[rstest]
+	#[case::default(None, None, Some(json!({"name": "aiven-token", "key": "token"})))]
+	#[case::from_annotation(
+		Some("team-token"),
+		None,
+		Some(json!({"name": "team-token", "key": "token"}))
+	)]
+	#[case::same_secret(None, Some(json!({"name": "aiven-token", "key": "token"})), None)]
+	#[case::allowed_secret(None, Some(json!({"name": "legacy-token", "key": "token"})), None)]
+	fn auth_secret_ref(
+		#[case] annotation: Option<&str>,
+		#[case] existing: Option<Value>,
+		#[case] expected: Option<Value>,
+	) {
+		let config = AuthSecretConfig {
+			name: "aiven-token".to_string(),
+			key: "token".to_string(),
+			allowed: vec!["legacy-token".to_string()],
+		};
+		let mut valkey = create_object(None);
+		if let Some(existing) = existing {
+			valkey.data["spec"]["authSecretRef"] = existing;
+		}
+		let namespace_meta = ObjectMeta {
+			annotations: annotation
+				.map(|v| BTreeMap::from([(AUTH_SECRET_ANNOTATION.to_string(), v.to_string())])),
+			..Default::default()
+		};
+		let mut patches = Vec::new();
+
+		add_auth_secret_ref(&config, &valkey, Some(&namespace_meta), &mut patches).unwrap();
+
+		let expected: Vec<PatchOperation> = expected
+			.map(|value| add_patch(valkey.auth_secret_ref_path(), value))
+			.into_iter()
+			.collect();
+		assert_eq!(patches, expected);
+	}
+
+	#[rstest]
+	fn auth_secret_ref_completes_partial_ref() {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["authSecretRef"] = json!({});
+		let mut patches = Vec::new();
+
+		add_auth_secret_ref(&AuthSecretConfig::default(), &valkey, None, &mut patches).unwrap();
+
+		let path = valkey.auth_secret_ref_path();
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(path.with_trailing_token("name"), json!("aiven-token")),
+				add_patch(path.with_trailing_token("key"), json!("token")),
+			]
+		);
+	}
+
+	#[rstest]
+	fn auth_secret_ref_denies_other_secrets() {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["authSecretRef"] = json!({"name": "other-team-token", "key": "token"});
+		let mut patches = Vec::new();
+
+		let result = add_auth_secret_ref(&AuthSecretConfig::default(), &valkey, None, &mut patches);
+
+		assert!(result.is_err());
+		assert_eq!(patches, vec![]);
+	}
+
+	#[rstest]
+	#[case::missing_version("PostgreSQL", json!({}), Some("pg_version"))]
+	#[case::user_version("PostgreSQL", json!({"pg_version": "15"}), None)]
+	#[case::no_default("MySQL", json!({}), None)]
+	#[case::no_version_field("Valkey", json!({}), None)]
+	fn default_version(
+		#[case] kind: &str,
+		#[case] user_config: Value,
+		#[case] expected: Option<&str>,
+	) {
+		let config = VersionConfig {
+			defaults: BTreeMap::from([
+				("PostgreSQL".to_string(), "17".to_string()),
+				("Valkey".to_string(), "8".to_string()),
+			]),
+			upgrades: Default::default(),
+		};
+		let mut obj = create_object(None);
+		obj.types.as_mut().unwrap().kind = kind.to_string();
+		obj.data["spec"]["userConfig"] = user_config;
+		let mut patches = Vec::new();
+
+		add_version(&config, &obj, None, &mut patches);
+
+		let expected: Vec<PatchOperation> = expected
+			.map(|field| add_patch(obj.user_config_value_path(field), json!("17")))
+			.into_iter()
+			.collect();
+		assert_eq!(patches, expected);
+	}
+
+	#[rstest]
+	fn default_version_keeps_previous() {
+		let config = VersionConfig {
+			defaults: BTreeMap::from([("PostgreSQL".to_string(), "17".to_string())]),
+			upgrades: Default::default(),
+		};
+		let mut old = create_object(None);
+		old.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		old.data["spec"]["userConfig"] = json!({"pg_version": "15"});
+		let mut obj = old.clone();
+		obj.data["spec"]["userConfig"] = json!({});
+		let mut patches = Vec::new();
+
+		add_version(&config, &obj, Some(&old), &mut patches);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				obj.user_config_value_path("pg_version"),
+				json!("15")
+			)]
+		);
+	}
+
+	#[rstest]
+	fn default_version_not_added_on_update() {
+		let config = VersionConfig {
+			defaults: BTreeMap::from([("PostgreSQL".to_string(), "17".to_string())]),
+			upgrades: Default::default(),
+		};
+		let mut old = create_object(None);
+		old.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		old.data["spec"]["userConfig"] = json!({});
+		let obj = old.clone();
+		let mut patches = Vec::new();
+
+		let version = add_version(&config, &obj, Some(&old), &mut patches);
+
+		assert_eq!(version, None);
+		assert_eq!(patches, vec![]);
+	}
+
+	#[rstest]
+	#[case::unchanged("15", "15", true)]
+	#[case::upgrade("15", "16", true)]
+	#[case::numeric_upgrade(15, 16, true)]
+	#[case::skipping_major("15", "17", false)]
+	#[case::downgrade("16", "15", false)]
+	#[case::unknown_version("12", "13", false)]
+	fn version_change<T: Into<Value>>(#[case] old: T, #[case] new: T, #[case] allowed: bool) {
+		let config = VersionConfig {
+			defaults: Default::default(),
+			upgrades: BTreeMap::from([(
+				"PostgreSQL".to_string(),
+				BTreeMap::from([
+					("15".to_string(), vec!["16".to_string()]),
+					("16".to_string(), vec!["17".to_string()]),
+				]),
+			)]),
+		};
+		let mut old_obj = create_object(None);
+		old_obj.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		old_obj.data["spec"]["userConfig"] = json!({"pg_version": old.into()});
+		let mut obj = old_obj.clone();
+		obj.data["spec"]["userConfig"] = json!({"pg_version": new.into()});
+
+		let result = validate_version_change(&config, &obj, Some(&old_obj));
+
+		assert_eq!(result.is_ok(), allowed, "{:?}", result);
+	}
+
+	#[rstest]
+	#[case::known("google-europe-north1", "hobbyist", None, None, true)]
+	#[case::unknown_cloud("google-atlantis1", "hobbyist", None, None, false)]
+	#[case::unknown_plan("google-europe-north1", "startup-5", None, None, false)]
+	#[case::unchanged_cloud("google-atlantis1", "hobbyist", Some("google-atlantis1"), None, true)]
+	#[case::unchanged_plan("google-europe-north1", "startup-5", None, Some("startup-5"), true)]
+	fn cloud_and_plan_are_validated_against_catalog(
+		#[case] cloud_name: &str,
+		#[case] plan: &str,
+		#[case] old_cloud_name: Option<&str>,
+		#[case] old_plan: Option<&str>,
+		#[case] allowed: bool,
+	) {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["plan"] = json!(plan);
+		let old_valkey = (old_cloud_name.is_some() || old_plan.is_some()).then(|| {
+			let mut old_valkey = create_object(None);
+			old_valkey.data["spec"]["cloudName"] = json!(old_cloud_name);
+			old_valkey.data["spec"]["plan"] = json!(old_plan);
+			old_valkey
+		});
+
+		let result = validate_catalog(
+			&catalog(),
+			cloud_name,
+			Some(plan),
+			None,
+			&valkey,
+			old_valkey.as_ref().map(|o| o as &dyn AivenObject),
+		);
+
+		assert_eq!(result.is_ok(), allowed, "{:?}", result);
+	}
+
+	#[rstest]
+	#[case::known("17", None, true)]
+	#[case::unknown("12", None, false)]
+	#[case::unchanged("12", Some("12"), true)]
+	fn version_is_validated_against_catalog(
+		#[case] version: &str,
+		#[case] old_version: Option<&str>,
+		#[case] allowed: bool,
+	) {
+		let mut obj = create_object(None);
+		obj.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		obj.data["spec"]["userConfig"] = json!({"pg_version": version});
+		let old_obj = old_version.map(|old_version| {
+			let mut old_obj = obj.clone();
+			old_obj.data["spec"]["userConfig"] = json!({"pg_version": old_version});
+			old_obj
+		});
+
+		let result = validate_catalog(
+			&catalog(),
+			"google-europe-north1",
+			None,
+			Some(version),
+			&obj,
+			old_obj.as_ref().map(|o| o as &dyn AivenObject),
+		);
+
+		assert_eq!(result.is_ok(), allowed, "{:?}", result);
+	}
+
+	#[rstest]
+	fn default_plan_is_validated_against_catalog() {
+		let config = PlanConfig {
+			defaults: BTreeMap::from([(
+				"*".to_string(),
+				BTreeMap::from([("*".to_string(), "startup-5".to_string())]),
+			)]),
+		};
+		let mut valkey = create_object(None);
+		valkey.data["spec"].as_object_mut().unwrap().remove("plan");
+		let mut patches = Vec::new();
+
+		let plan = add_plan(&config, "dev", &valkey, &mut patches);
+		let result = validate_catalog(
+			&catalog(),
+			"google-europe-north1",
+			plan.as_deref(),
+			None,
+			&valkey,
+			None,
+		);
+
+		assert!(result.is_err());
+	}
+
+	#[rstest]
+	fn version_change_message_lists_allowed_versions() {
+		let config = VersionConfig {
+			defaults: Default::default(),
+			upgrades: BTreeMap::from([(
+				"PostgreSQL".to_string(),
+				BTreeMap::from([("15".to_string(), vec!["16".to_string()])]),
+			)]),
+		};
+		let mut old_obj = create_object(None);
+		old_obj.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		old_obj.data["spec"]["userConfig"] = json!({"pg_version": "15"});
+		let mut obj = old_obj.clone();
+		obj.data["spec"]["userConfig"] = json!({"pg_version": "14"});
+
+		let err = validate_version_change(&config, &obj, Some(&old_obj)).unwrap_err();
+
+		assert_eq!(
+			err.to_string(),
+			"pg_version may not change from 15 to 14, allowed next versions: 16"
+		);
+	}
+
+	fn fields_config() -> FieldsConfig {
+		FieldsConfig {
+			forbidden: BTreeMap::from([
+				(
+					"*".to_string(),
+					vec![ForbiddenField {
+						pointer: PointerBuf::from_tokens(["spec", "userConfig", "public_access"]),
+						action: FieldAction::Remove,
+						reason: None,
+					}],
+				),
+				(
+					"Valkey".to_string(),
+					vec![ForbiddenField {
+						pointer: PointerBuf::from_tokens(["spec", "userConfig", "static_ips"]),
+						action: FieldAction::Deny,
+						reason: Some("static IPs are not allowed".to_string()),
+					}],
+				),
+			]),
+		}
+	}
+
+	#[rstest]
+	fn forbidden_field_is_removed() {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["userConfig"] = json!({
+			"public_access": {"valkey": true},
+			"valkey_timeout": 300
+		});
+		let mut patches = Vec::new();
+
+		let stripped = remove_forbidden_fields(&fields_config(), &valkey, &mut patches).unwrap();
+
+		assert_eq!(
+			patches,
+			vec![remove_patch(valkey.user_config_value_path("public_access"))]
+		);
+		assert_eq!(
+			stripped.get_user_config(),
+			Some(json!({"valkey_timeout": 300}).as_object().unwrap().clone())
+		);
+	}
+
+	#[rstest]
+	fn forbidden_field_for_kind_and_any_kind_is_removed_once() {
+		let mut config = fields_config();
+		config
+			.forbidden
+			.get_mut("Valkey")
+			.unwrap()
+			.push(ForbiddenField {
+				pointer: PointerBuf::from_tokens(["spec", "userConfig", "public_access"]),
+				action: FieldAction::Remove,
+				reason: None,
+			});
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["userConfig"] = json!({"public_access": {"valkey": true}});
+		let mut patches = Vec::new();
+
+		remove_forbidden_fields(&config, &valkey, &mut patches).unwrap();
+
+		assert_eq!(
+			patches,
+			vec![remove_patch(valkey.user_config_value_path("public_access"))]
+		);
+	}
+
+	#[rstest]
+	fn forbidden_field_is_denied() {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["userConfig"] = json!({"static_ips": true});
+		let mut patches = Vec::new();
+
+		let err = remove_forbidden_fields(&fields_config(), &valkey, &mut patches).unwrap_err();
+
+		assert_eq!(
+			err.to_string(),
+			"/spec/userConfig/static_ips is not allowed: static IPs are not allowed"
+		);
+	}
+
+	#[rstest]
+	fn forbidden_field_for_other_kind_is_ignored() {
+		let mut postgres = create_object(None);
+		postgres.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		postgres.data["spec"]["userConfig"] = json!({"static_ips": true});
+		let mut patches = Vec::new();
+
+		remove_forbidden_fields(&fields_config(), &postgres, &mut patches).unwrap();
+
+		assert_eq!(patches, vec![]);
+	}
+
+	fn user_config_defaults() -> UserConfigConfig {
+		UserConfigConfig {
+			defaults: BTreeMap::from([(
+				"Valkey".to_string(),
+				json!({
+					"valkey_maxmemory_policy": "allkeys-lru",
+					"valkey_timeout": 300,
+					"migration": {"method": "replication", "ssl": true}
+				})
+				.as_object()
+				.cloned()
+				.unwrap(),
+			)]),
+		}
+	}
+
+	#[rstest]
+	fn user_config_defaults_without_user_config() {
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		merge_user_config_defaults(&user_config_defaults(), &valkey, &mut patches);
+
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(valkey.user_config_path(), json!({})),
+				add_patch(
+					valkey.user_config_value_path("migration"),
+					json!({"method": "replication", "ssl": true})
+				),
+				add_patch(
+					valkey.user_config_value_path("valkey_maxmemory_policy"),
+					json!("allkeys-lru")
+				),
+				add_patch(valkey.user_config_value_path("valkey_timeout"), json!(300)),
+			]
+		);
+	}
+
+	#[rstest]
+	fn user_config_defaults_only_fill_missing_keys() {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["userConfig"] = json!({
+			"valkey_timeout": 60,
+			"migration": {"method": "dump"}
+		});
+		let mut patches = vec![add_patch(
+			valkey.user_config_value_path("valkey_maxmemory_policy"),
+			json!("noeviction"),
+		)];
+
+		merge_user_config_defaults(&user_config_defaults(), &valkey, &mut patches);
+
+		assert_eq!(
+			patches[1..],
+			vec![add_patch(
+				PointerBuf::from_tokens(["spec", "userConfig", "migration", "ssl"]),
+				json!(true)
+			)]
+		);
+	}
+
+	#[rstest]
+	fn user_config_defaults_after_forbidden_user_config() {
+		let config = FieldsConfig {
+			forbidden: BTreeMap::from([(
+				"Valkey".to_string(),
+				vec![ForbiddenField {
+					pointer: PointerBuf::from_tokens(["spec", "userConfig"]),
+					action: FieldAction::Remove,
+					reason: None,
+				}],
+			)]),
+		};
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["userConfig"] = json!({"valkey_timeout": 60});
+		let mut patches = Vec::new();
+
+		let stripped = remove_forbidden_fields(&config, &valkey, &mut patches).unwrap();
+		merge_user_config_defaults(&user_config_defaults(), stripped.as_ref(), &mut patches);
+		json_patch::patch(&mut valkey.data, &patches).unwrap();
+
+		assert_eq!(
+			valkey.data["spec"]["userConfig"],
+			json!({
+				"valkey_maxmemory_policy": "allkeys-lru",
+				"valkey_timeout": 300,
+				"migration": {"method": "replication", "ssl": true}
+			})
+		);
+	}
+
+	#[rstest]
+	fn add_tags_with_extra_tags(config: Arc<AppConfig>) {
+		let mut config = (*config).clone();
+		config.tags = TagsConfig {
+			extra: BTreeMap::from([
+				("owner".to_string(), "{tenant}-{team}".to_string()),
+				("team".to_string(), "not-the-team".to_string()),
+			]),
+			namespace_labels: BTreeMap::from([(
+				"nais.io/cost-center".to_string(),
+				"cost-center".to_string(),
+			)]),
+			namespace_annotations: BTreeMap::from([(
+				"nais.io/product-area".to_string(),
+				"product-area".to_string(),
+			)]),
+			reserved: Default::default(),
+			reserved_prefixes: Default::default(),
+			sources: Default::default(),
+		};
+		let namespace_meta = ObjectMeta {
+			labels: Some(BTreeMap::from([(
+				"nais.io/cost-center".to_string(),
+				"1234".to_string(),
+			)])),
+			..Default::default()
+		};
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		add_tags(
+			&Arc::new(config),
+			Some(NAMESPACE.to_string()),
+			Some(&namespace_meta),
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				valkey.tags_path(),
+				json!({
+					"environment": ENVIRONMENT,
+					"tenant": TENANT,
+					"team": NAMESPACE,
+					"owner": format!("{}-{}", TENANT, NAMESPACE),
+					"cost-center": "1234",
+				})
+			)]
+		);
+	}
+
+	#[rstest]
+	fn reserved_tags_are_removed_or_overwritten(config: Arc<AppConfig>) {
+		let mut config = (*config).clone();
+		config.tags.reserved = vec!["cost-center".to_string()];
+		config.tags.reserved_prefixes = vec!["nais-".to_string()];
+		let valkey = create_object(Some(BTreeMap::from([
+			("environment".to_string(), ENVIRONMENT.to_string()),
+			("tenant".to_string(), TENANT.to_string()),
+			("team".to_string(), "other-team".to_string()),
+			("cost-center".to_string(), "1234".to_string()),
+			("nais-owned".to_string(), "yes".to_string()),
+			("app".to_string(), "my-app".to_string()),
+		])));
+		let mut patches = Vec::new();
+		let mut warnings = Vec::new();
+
+		add_tags(
+			&Arc::new(config),
+			Some(NAMESPACE.to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut warnings,
+		);
+
+		assert_eq!(
+			patches,
+			vec![
+				remove_patch(valkey.tag_path("cost-center")),
+				remove_patch(valkey.tag_path("nais-owned")),
+				replace_patch(valkey.tag_path("team"), json!(NAMESPACE)),
+			]
+		);
+		assert_eq!(
+			warnings,
+			vec![
+				"tag cost-center is reserved and was removed".to_string(),
+				"tag nais-owned is reserved and was removed".to_string(),
+				format!("tag team is reserved and was set to {:?}", NAMESPACE),
+			]
+		);
+	}
+
+	#[rstest]
+	fn add_tags_sanitizes_values(config: Arc<AppConfig>) {
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		add_tags(
+			&config,
+			Some("Team_Æ".to_string()),
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				valkey.tags_path(),
+				json!({
+					"environment": ENVIRONMENT,
+					"tenant": TENANT,
+					"team": "team_-",
+				})
+			)]
+		);
+	}
+
+	#[rstest]
+	#[case::valid(json!({"app": "my-app", "url": "https://example.com/x"}), true)]
+	#[case::invalid_key(json!({"nais.io/app": "my-app"}), false)]
+	#[case::invalid_value(json!({"app": "my app!"}), false)]
+	#[case::owned_tag_is_left_to_mutilator(json!({"team": "Not Valid!"}), true)]
+	fn validate_user_tags(config: Arc<AppConfig>, #[case] tags: Value, #[case] valid: bool) {
+		let valkey = create_object_with_tags(tags);
+
+		let result = validate_tags(&config, &["team".to_string()], &valkey);
+
+		assert_eq!(result.is_ok(), valid, "{:?}", result);
+	}
+
+	#[rstest]
+	#[case::created_by("created-by", false)]
+	#[case::extra("owner", true)]
+	#[case::namespace_label("cost-center", false)]
+	#[case::source("app", false)]
+	#[case::reserved("nais-internal", true)]
+	fn validate_tags_leaves_out_owned_tags(
+		config: Arc<AppConfig>,
+		#[case] tag_name: &str,
+		#[case] valid: bool,
+	) {
+		let mut config = (*config).clone();
+		config.tags.extra = BTreeMap::from([("owner".to_string(), "{team}".to_string())]);
+		config.tags.namespace_labels =
+			BTreeMap::from([("nais.io/cost-center".to_string(), "cost-center".to_string())]);
+		config.tags.reserved = vec!["nais-internal".to_string()];
+		config.tags.sources = vec![SourceTagRule {
+			tag: "app".to_string(),
+			label: Some("app".to_string()),
+			annotation: None,
+			pattern: None,
+			value: None,
+		}];
+		let config = Arc::new(config);
+		let valkey = create_object_with_tags(json!({ tag_name: "Not Valid!" }));
+		let computed = source_tags(&config.tags, &valkey);
+
+		// Only tags add_tags overwrites are left out, here the team derived extra tag
+		let owned = add_tags(
+			&config,
+			Some("team".to_string()),
+			None,
+			computed,
+			&valkey,
+			&mut Vec::new(),
+			&mut Vec::new(),
+		);
+		let result = validate_tags(&config, &owned, &valkey);
+
+		assert_eq!(result.is_ok(), valid, "{:?}", result);
+	}
+
+	#[rstest]
+	fn validate_tags_explains_problems(config: Arc<AppConfig>) {
+		let valkey = create_object_with_tags(json!({"1app": "my app!"}));
+
+		let err = validate_tags(&config, &[], &valkey).unwrap_err();
+
+		assert_eq!(
+			err.to_string(),
+			"invalid tags: tag key \"1app\" must start with a letter, value of tag 1app may only contain letters, digits, spaces and any of '_=.:/@+-'"
+		);
+	}
+
+	#[rstest]
+	#[case::plain_user(None, Some("jane@example.com"), Some("jane@example.com"))]
+	#[case::mapped_user(Some("^([^@]+)@"), Some("jane@example.com"), Some("jane"))]
+	#[case::service_account(
+		Some("^system:serviceaccount:[^:]+:(.+)$|^([^@]+)@"),
+		Some("system:serviceaccount:argocd:argocd-application-controller"),
+		Some("argocd-application-controller")
+	)]
+	#[case::alternative_group(
+		Some("^system:serviceaccount:[^:]+:(.+)$|^([^@]+)@"),
+		Some("jane@example.com"),
+		Some("jane")
+	)]
+	#[case::unmatched(Some("^([^@]+)@"), Some("deployer"), Some("deployer"))]
+	#[case::no_user(None, None, None)]
+	fn created_by_on_create(
+		#[case] pattern: Option<&str>,
+		#[case] username: Option<&str>,
+		#[case] expected: Option<&str>,
+	) {
+		let config = CreatedByConfig {
+			pattern: pattern.map(|p| p.parse().unwrap()),
+			..Default::default()
+		};
+
+		let created_by = resolve_created_by(&config, username, None);
+
+		assert_eq!(created_by.as_deref(), expected);
+	}
+
+	#[rstest]
+	fn created_by_is_kept_on_update() {
+		let config = CreatedByConfig::default();
+		let mut old = create_object(None);
+		old.metadata.annotations = Some(BTreeMap::from([(
+			config.annotation.clone(),
+			"jane".to_string(),
+		)]));
+		let mut valkey = old.clone();
+		valkey.metadata.annotations = Some(BTreeMap::from([(
+			config.annotation.clone(),
+			"someone-else".to_string(),
+		)]));
+
+		let created_by = resolve_created_by(&config, Some("john"), Some(&old));
+		let mut patches = Vec::new();
+		add_created_by_annotation(&config, created_by.as_deref(), &valkey, &mut patches);
+
+		assert_eq!(created_by.as_deref(), Some("jane"));
+		assert_eq!(
+			patches,
+			vec![replace_patch(
+				PointerBuf::from_tokens([
+					"metadata",
+					"annotations",
+					"mutilator.nais.io/created-by"
+				]),
+				json!("jane")
+			)]
+		);
+	}
+
+	#[rstest]
+	fn created_by_annotation_added() {
+		let config = CreatedByConfig::default();
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		add_created_by_annotation(&config, Some("jane"), &valkey, &mut patches);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				valkey.annotations_path(),
+				json!({"mutilator.nais.io/created-by": "jane"})
+			)]
+		);
+	}
+
+	fn source_tag_rules() -> TagsConfig {
+		let rule = |tag: &str, label: Option<&str>, annotation: Option<&str>| SourceTagRule {
+			tag: tag.to_string(),
+			label: label.map(|l| l.to_string()),
+			annotation: annotation.map(|a| a.to_string()),
+			pattern: None,
+			value: None,
+		};
+		TagsConfig {
+			sources: vec![
+				SourceTagRule {
+					value: Some("argocd".to_string()),
+					..rule("source", None, Some("argocd.argoproj.io/tracking-id"))
+				},
+				SourceTagRule {
+					pattern: Some("^([^:]+):".parse().unwrap()),
+					..rule("app", None, Some("argocd.argoproj.io/tracking-id"))
+				},
+				rule("source", Some("app.kubernetes.io/managed-by"), None),
+				rule("app", Some("app"), None),
+				SourceTagRule {
+					pattern: Some("github.com/([^/]+/[^/]+)/".parse().unwrap()),
+					..rule("repo", None, Some("deploy.nais.io/github-workflow-run-url"))
+				},
+			],
+			..Default::default()
+		}
+	}
+
+	#[rstest]
+	fn source_tags_from_argocd() {
+		let mut valkey = create_object(None);
+		valkey.metadata.annotations = Some(BTreeMap::from([(
+			"argocd.argoproj.io/tracking-id".to_string(),
+			"my-app:aiven.io/Valkey:my-team/my-valkey".to_string(),
+		)]));
+		valkey.metadata.labels = Some(BTreeMap::from([(
+			"app.kubernetes.io/managed-by".to_string(),
+			"Helm".to_string(),
+		)]));
+
+		let tags = source_tags(&source_tag_rules(), &valkey);
+
+		assert_eq!(
+			tags,
+			vec![
+				("source".to_string(), "argocd".to_string()),
+				("app".to_string(), "my-app".to_string()),
+			]
+		);
+	}
+
+	#[rstest]
+	fn source_tags_from_deploy_pipeline() {
+		let mut valkey = create_object(None);
+		valkey.metadata.annotations = Some(BTreeMap::from([(
+			"deploy.nais.io/github-workflow-run-url".to_string(),
+			"https://github.com/nais/mutilator/actions/runs/1234".to_string(),
+		)]));
+		valkey.metadata.labels = Some(BTreeMap::from([
+			(
+				"app.kubernetes.io/managed-by".to_string(),
+				"nais-deploy".to_string(),
+			),
+			("app".to_string(), "mutilator".to_string()),
+		]));
+
+		let tags = source_tags(&source_tag_rules(), &valkey);
+
+		assert_eq!(
+			tags,
+			vec![
+				("source".to_string(), "nais-deploy".to_string()),
+				("app".to_string(), "mutilator".to_string()),
+				("repo".to_string(), "nais/mutilator".to_string()),
+			]
+		);
+	}
+
+	#[rstest]
+	#[case::provider_and_location(None, None, None, format!("google-{}", LOCATION))]
+	#[case::cloud_name(Some("aws-eu-north-1"), None, None, "aws-eu-north-1".to_string())]
+	#[case::kind(Some("aws-eu-north-1"), Some("azure-norway-east"), None, "azure-norway-east".to_string())]
+	#[case::namespace(
+		Some("aws-eu-north-1"),
+		Some("azure-norway-east"),
+		Some("upcloud-fi-hel1"),
+		"upcloud-fi-hel1".to_string()
+	)]
+	fn cloud_name(
+		config: Arc<AppConfig>,
+		#[case] name: Option<&str>,
+		#[case] kind: Option<&str>,
+		#[case] namespace: Option<&str>,
+		#[case] expected: String,
+	) {
+		let mut config = (*config).clone();
+		config.cloud.name = name.map(|n| n.to_string());
+		config.cloud.kinds = kind
+			.map(|k| BTreeMap::from([("Valkey".to_string(), k.to_string())]))
+			.unwrap_or_default();
+		config.cloud.namespaces = namespace
+			.map(|n| BTreeMap::from([("test-namespace".to_string(), n.to_string())]))
+			.unwrap_or_default();
+		let valkey = create_object(None);
+
+		let cloud = resolve_cloud_name(&config, &valkey, None).unwrap();
+
+		assert_eq!(cloud.name, expected);
+		assert_eq!(cloud.pinned, kind.is_some() || namespace.is_some());
+	}
+
+	#[rstest]
+	#[case::allowed("europe-west1", true)]
+	#[case::not_allowed("us-east1", false)]
+	fn cloud_name_from_namespace_annotation(
+		config: Arc<AppConfig>,
+		#[case] location: &str,
+		#[case] allowed: bool,
+	) {
+		let mut config = (*config).clone();
+		config.cloud.allowed_locations = vec![LOCATION.to_string(), "europe-west1".to_string()];
+		config.cloud.namespaces =
+			BTreeMap::from([("test-namespace".to_string(), "aws-eu-north-1".to_string())]);
+		let namespace_meta = ObjectMeta {
+			annotations: Some(BTreeMap::from([(
+				LOCATION_ANNOTATION.to_string(),
+				location.to_string(),
+			)])),
+			..Default::default()
+		};
+		let valkey = create_object(None);
+
+		let cloud_name = resolve_cloud_name(&config, &valkey, Some(&namespace_meta));
+
+		match allowed {
+			true => assert_eq!(
+				cloud_name.unwrap(),
+				ResolvedCloud {
+					name: format!("google-{}", location),
+					pinned: true,
+				}
+			),
+			false => assert!(cloud_name.is_err()),
+		}
+	}
+
+	#[rstest]
+	#[case::missing(None, &[], "google-europe-north1", Some("add"))]
+	#[case::same(Some("google-europe-north1"), &["europe-north1"], "google-europe-north1", None)]
+	#[case::overwritten_when_unrestricted(Some("google-us-east1"), &[], "google-europe-north1", Some("replace"))]
+	#[case::allowed(Some("google-europe-west1"), &["europe-north1", "europe-west1"], "google-europe-west1", None)]
+	fn location(
+		#[case] existing: Option<&str>,
+		#[case] allowed_locations: &[&str],
+		#[case] expected_name: &str,
+		#[case] expected_op: Option<&str>,
+	) {
+		let config = CloudConfig {
+			allowed_locations: allowed_locations.iter().map(|l| l.to_string()).collect(),
+			..Default::default()
+		};
+		let mut valkey = create_object(None);
+		if let Some(existing) = existing {
+			valkey.data["spec"]["cloudName"] = json!(existing);
+		}
+		let mut patches = Vec::new();
+
+		let cloud_name =
+			add_location(&config, default_cloud(), &valkey, None, &mut patches).unwrap();
+
+		let expected: Vec<PatchOperation> = match expected_op {
+			Some("add") => vec![add_patch(valkey.cloud_name_path(), json!(expected_name))],
+			Some(_) => vec![replace_patch(
+				valkey.cloud_name_path(),
+				json!(expected_name),
+			)],
+			None => vec![],
+		};
+		assert_eq!(cloud_name, expected_name);
+		assert_eq!(patches, expected);
+	}
+
+	#[rstest]
+	#[case::default_cloud(format!("google-{}", LOCATION), Some(PROJECT_VPC_ID))]
+	#[case::other_cloud("google-europe-west1".to_string(), Some("other-vpc-id"))]
+	#[case::unknown_cloud("aws-eu-north-1".to_string(), None)]
+	fn project_vpc_id_for_cloud(
+		config: Arc<AppConfig>,
+		#[case] cloud_name: String,
+		#[case] expected: Option<&str>,
+	) {
+		let mut config = (*config).clone();
+		config.vpc.ids = BTreeMap::from([(
+			"google-europe-west1".to_string(),
+			"other-vpc-id".to_string(),
+		)]);
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		let result = add_project_vpc_id(&config, &cloud_name, &valkey, &mut patches);
+
+		match expected {
+			Some(id) => {
+				result.unwrap();
+				assert_eq!(
+					patches,
+					vec![add_patch(valkey.project_vpc_id_path(), json!(id))]
+				);
+			},
+			None => assert!(result.is_err()),
+		}
+	}
+
+	#[rstest]
+	fn project_vpc_id_set_by_user_is_kept(config: Arc<AppConfig>) {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["projectVpcId"] = json!("user-vpc-id");
+		let mut patches = Vec::new();
+
+		add_project_vpc_id(&config, "aws-eu-north-1", &valkey, &mut patches).unwrap();
+
+		assert_eq!(patches, vec![]);
+	}
+
+	#[rstest]
+	#[case::configured(PROJECT_VPC_ID, None, true)]
+	#[case::other_cloud("other-vpc-id", None, true)]
+	#[case::unknown("unknown-vpc-id", None, false)]
+	#[case::unchanged("unknown-vpc-id", Some("unknown-vpc-id"), true)]
+	#[case::changed("unknown-vpc-id", Some(PROJECT_VPC_ID), false)]
+	fn project_vpc_id_is_validated(
+		config: Arc<AppConfig>,
+		#[case] id: &str,
+		#[case] old_id: Option<&str>,
+		#[case] allowed: bool,
+	) {
+		let mut config = (*config).clone();
+		config.vpc.ids = BTreeMap::from([(
+			"google-europe-west1".to_string(),
+			"other-vpc-id".to_string(),
+		)]);
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["projectVpcId"] = json!(id);
+		let old_valkey = old_id.map(|old_id| {
+			let mut old_valkey = create_object(None);
+			old_valkey.data["spec"]["projectVpcId"] = json!(old_id);
+			old_valkey
+		});
+
+		let result = validate_project_vpc_id(
+			&config,
+			&valkey,
+			old_valkey.as_ref().map(|o| o as &dyn AivenObject),
+		);
+
+		assert_eq!(result.is_ok(), allowed);
+	}
+
+	#[rstest]
+	fn project_vpc_ref_is_kept_unresolved(config: Arc<AppConfig>) {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["projectVPCRef"] = json!({"name": "any-vpc"});
+
+		let uses_ref = check_project_vpc_ref(&config, &cluster(None), &valkey).unwrap();
+
+		assert!(uses_ref);
+	}
+
+	#[rstest]
+	fn project_vpc_ref_and_id_is_denied(config: Arc<AppConfig>) {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["projectVPCRef"] = json!({"name": "any-vpc"});
+		valkey.data["spec"]["projectVpcId"] = json!(PROJECT_VPC_ID);
+
+		let result = check_project_vpc_ref(&config, &cluster(None), &valkey);
+
+		assert!(result.is_err());
+	}
+
+	#[rstest]
+	#[case::tenant_vpc(json!({"name": "tenant-vpc"}), true)]
+	#[case::other_namespace(json!({"name": "tenant-vpc", "namespace": "shared"}), true)]
+	#[case::not_ready(json!({"name": "new-vpc"}), false)]
+	#[case::foreign_vpc(json!({"name": "foreign-vpc"}), false)]
+	#[case::missing_vpc(json!({"name": "missing-vpc"}), false)]
+	#[case::missing_name(json!({"namespace": NAMESPACE}), false)]
+	fn project_vpc_ref_is_resolved(
+		config: Arc<AppConfig>,
+		#[case] vpc_ref: Value,
+		#[case] allowed: bool,
+	) {
+		let vpcs = [
+			(NAMESPACE, "tenant-vpc", Some(PROJECT_VPC_ID)),
+			("shared", "tenant-vpc", Some(PROJECT_VPC_ID)),
+			(NAMESPACE, "new-vpc", None),
+			(NAMESPACE, "foreign-vpc", Some("foreign-vpc-id")),
+		];
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["projectVPCRef"] = vpc_ref;
+
+		let result = check_project_vpc_ref(&config, &cluster(Some(&vpcs)), &valkey);
+
+		assert_eq!(result.is_ok(), allowed);
+	}
+
+	#[rstest]
+	fn project_vpc_ref_missing_is_ignored(config: Arc<AppConfig>) {
+		let valkey = create_object(None);
+
+		let uses_ref = check_project_vpc_ref(&config, &cluster(None), &valkey).unwrap();
+
+		assert!(!uses_ref);
+	}
+
+	#[rstest]
+	fn location_outside_allowed_is_denied() {
+		let config = CloudConfig {
+			allowed_locations: vec!["europe-north1".to_string()],
+			..Default::default()
+		};
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["cloudName"] = json!("aws-us-east-1");
+		let mut patches = Vec::new();
+
+		let result = add_location(&config, default_cloud(), &valkey, None, &mut patches);
+
+		assert!(result.is_err());
+	}
+
+	#[rstest]
+	#[case::same("google-europe-west1", None, true)]
+	#[case::other_allowed_location("google-europe-north1", None, false)]
+	#[case::unchanged_on_update("google-europe-north1", Some("google-europe-north1"), true)]
+	#[case::changed_on_update("google-europe-north1", Some("aws-eu-north-1"), false)]
+	fn pinned_cloud_name_is_enforced(
+		#[case] existing: &str,
+		#[case] old: Option<&str>,
+		#[case] allowed: bool,
+	) {
+		let config = CloudConfig {
+			allowed_locations: vec!["europe-north1".to_string(), "europe-west1".to_string()],
+			..Default::default()
+		};
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["cloudName"] = json!(existing);
+		let old_valkey = old.map(|old| {
+			let mut old_valkey = valkey.clone();
+			old_valkey.data["spec"]["cloudName"] = json!(old);
+			old_valkey
+		});
+		let pinned = ResolvedCloud {
+			name: "google-europe-west1".to_string(),
+			pinned: true,
+		};
+		let mut patches = Vec::new();
+
+		let result = add_location(
+			&config,
+			pinned,
+			&valkey,
+			old_valkey.as_ref().map(|o| o as &dyn AivenObject),
+			&mut patches,
+		);
+
+		assert_eq!(result.is_ok(), allowed, "{:?}", result);
+		assert_eq!(patches, vec![]);
+	}
+
+	fn default_cloud() -> ResolvedCloud {
+		ResolvedCloud {
+			name: "google-europe-north1".to_string(),
+			pinned: false,
+		}
+	}
+
+	#[rstest]
+	fn add_tags_without_team(config: Arc<AppConfig>) {
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		add_tags(
+			&config,
+			None,
+			None,
+			Vec::new(),
+			&valkey,
+			&mut patches,
+			&mut Vec::new(),
+		);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				valkey.tags_path(),
+				json!({
+					"environment": ENVIRONMENT,
+					"tenant": TENANT,
+				})
+			)]
+		);
+	}
+
+	#[rstest]
+	fn maintenance_window_is_deterministic() {
+		let config = MaintenanceWindowConfig::default();
+		let valkey = create_object(None);
+		let mut first = Vec::new();
+		let mut second = Vec::new();
+
+		add_maintenance_window(&config, &valkey, None, &mut first);
+		add_maintenance_window(&config, &valkey, None, &mut second);
+
+		assert_eq!(first.len(), 2);
+		assert_eq!(first, second);
+	}
+
+	#[rstest]
+	fn maintenance_window_is_spread() {
+		let config = MaintenanceWindowConfig::default();
+		let mut windows = BTreeSet::new();
+		for i in 0..50 {
+			let mut valkey = create_object(None);
+			valkey.metadata.name = Some(format!("valkey-{}", i));
+			let mut patches = Vec::new();
+			add_maintenance_window(&config, &valkey, None, &mut patches);
+			windows.insert(serde_json::to_string(&patches).unwrap());
+		}
+
+		assert!(
+			windows.len() > 10,
+			"only {} distinct windows",
+			windows.len()
+		);
+	}
+
+	#[rstest]
+	fn maintenance_window_within_allowed_values() {
+		let config = MaintenanceWindowConfig {
+			days: vec![Weekday::Saturday],
+			hours: vec![22],
+		};
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
+
+		add_maintenance_window(&config, &valkey, None, &mut patches);
+
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(valkey.maintenance_window_dow_path(), json!("saturday")),
+				add_patch(valkey.maintenance_window_time_path(), json!("22:00:00")),
+			]
+		);
 	}
-}
 
-#[instrument(skip_all)]
-pub fn add_termination_protection(obj: &Box<dyn AivenObject>, patches: &mut Vec<PatchOperation>) {
-	if obj.get_termination_protection().is_none() {
-		info!("Enabling terminationProtection");
-		patches.push(add_patch(
-			obj.termination_protection_path(),
-			Value::Bool(true),
-		));
+	#[rstest]
+	fn maintenance_window_kept_from_old_object() {
+		let config = MaintenanceWindowConfig {
+			days: vec![Weekday::Saturday],
+			hours: vec![22],
+		};
+		let valkey = create_object(None);
+		let mut old_valkey = create_object(None);
+		old_valkey.data["spec"]["maintenanceWindowDow"] = json!("sunday");
+		old_valkey.data["spec"]["maintenanceWindowTime"] = json!("03:00:00");
+		let mut patches = Vec::new();
+
+		add_maintenance_window(&config, &valkey, Some(&old_valkey), &mut patches);
+
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(valkey.maintenance_window_dow_path(), json!("sunday")),
+				add_patch(valkey.maintenance_window_time_path(), json!("03:00:00")),
+			]
+		);
 	}
-}
 
-#[instrument(skip_all)]
-pub fn add_project_vpc_id(
-	project_vpc_id: String,
-	obj: &Box<dyn AivenObject>,
-	patches: &mut Vec<PatchOperation>,
-) {
-	if obj.get_project_vpc_id().is_none() {
-		info!("Adding projectVpcId");
-		patches.push(add_patch(
-			obj.project_vpc_id_path(),
-			Value::String(project_vpc_id),
-		));
+	#[rstest]
+	fn maintenance_window_set_by_user_is_kept() {
+		let config = MaintenanceWindowConfig::default();
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["maintenanceWindowDow"] = json!("sunday");
+		valkey.data["spec"]["maintenanceWindowTime"] = json!("03:00:00");
+		let mut patches = Vec::new();
+
+		add_maintenance_window(&config, &valkey, None, &mut patches);
+
+		assert_eq!(patches, vec![]);
 	}
-}
 
-fn add_patch(path: String, value: Value) -> PatchOperation {
-	PatchOperation::Add(json_patch::AddOperation {
-		path: PointerBuf::from_str(&path).unwrap(),
-		value,
-	})
-}
+	#[rstest]
+	#[case::postgresql("PostgreSQL")]
+	#[case::mysql("MySQL")]
+	fn backup_time_within_range(#[case] kind: &str) {
+		let config = BackupConfig {
+			time_ranges: vec!["23:50-00:10".parse().unwrap()],
+		};
+		for i in 0..20 {
+			let mut obj = create_object(None);
+			obj.types.as_mut().unwrap().kind = kind.to_string();
+			obj.metadata.name = Some(format!("db-{}", i));
+			let mut patches = Vec::new();
 
-fn replace_patch(path: String, value: Value) -> PatchOperation {
-	PatchOperation::Replace(json_patch::ReplaceOperation {
-		path: PointerBuf::from_str(&path).unwrap(),
-		value,
-	})
-}
+			add_backup_time(&config, &obj, &mut patches);
 
-#[cfg(test)]
-mod tests {
-	use std::collections::{BTreeMap, BTreeSet};
+			assert_eq!(patches.len(), 3);
+			assert_eq!(patches[0], add_patch(obj.user_config_path(), json!({})));
+			let hour = add_value(&patches[1]).as_u64().unwrap();
+			let minute = add_value(&patches[2]).as_u64().unwrap();
+			assert!(
+				(hour == 23 && minute >= 50) || (hour == 0 && minute < 10),
+				"{:02}:{:02} outside range",
+				hour,
+				minute
+			);
+		}
+	}
 
-	use kube::core::DynamicObject;
-	use pretty_assertions::assert_eq;
-	use rstest::*;
+	#[rstest]
+	fn backup_time_keeps_user_values() {
+		let config = BackupConfig::default();
+		let mut obj = create_object(None);
+		obj.types.as_mut().unwrap().kind = "PostgreSQL".to_string();
+		obj.data["spec"]["userConfig"] = json!({"backup_hour": 12});
+		let mut patches = Vec::new();
 
-	use crate::settings::{LogLevel, Tenant, WebConfig};
+		add_backup_time(&config, &obj, &mut patches);
 
-	use super::*;
+		assert_eq!(patches.len(), 1);
+		assert_eq!(
+			patches[0],
+			add_patch(
+				obj.user_config_value_path("backup_minute"),
+				add_value(&patches[0]).clone()
+			)
+		);
+	}
 
-	const ENVIRONMENT: &str = "test-tenant-env";
-	const TENANT: &str = "test-tenant-name";
-	const NAMESPACE: &str = "test-namespace";
-	const PROJECT_VPC_ID: &str = "test-vpc-id";
-	const LOCATION: &str = "test-location";
+	#[rstest]
+	fn backup_time_ignores_unsupported_kinds() {
+		let config = BackupConfig::default();
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
 
-	const TAG_PAIRS: [(&str, &str); 3] = [
-		("environment", ENVIRONMENT),
-		("tenant", TENANT),
-		("team", NAMESPACE),
-	];
+		add_backup_time(&config, &valkey, &mut patches);
 
-	#[fixture]
-	pub fn config() -> Arc<AppConfig> {
-		Arc::new(AppConfig {
-			log_format: Default::default(),
-			log_level: LogLevel::Trace,
-			web: WebConfig {
-				bind_address: "".to_string(),
-				certificate_path: None,
-				private_key_path: None,
+		assert_eq!(patches, vec![]);
+	}
+
+	fn ip_filter_config(policy: PolicyAction) -> IpFilterConfig {
+		serde_json::from_value(json!({
+			"sets": {
+				"cluster-egress": ["10.0.0.0/8"],
+				"onprem": ["192.168.0.0/16", "172.16.0.0/12"],
+				"office": ["192.168.1.0/24"]
 			},
-			tenant: Tenant {
-				environment: ENVIRONMENT.to_string(),
-				name: TENANT.to_string(),
+			"defaults": {
+				"*": {"*": ["cluster-egress"]},
+				"Valkey": {"prod": ["cluster-egress", "onprem"]}
 			},
-			project_vpc_id: PROJECT_VPC_ID.to_string(),
-			location: LOCATION.to_string(),
-			otel_enabled: false,
-		})
+			"policy": policy
+		}))
+		.unwrap()
 	}
 
 	#[rstest]
-	fn add_tags_when_no_tags_before(config: Arc<AppConfig>) {
+	#[case::default("dev", json!([
+		{"network": "10.0.0.0/8", "description": "cluster-egress"}
+	]))]
+	#[case::per_environment("prod", json!([
+		{"network": "10.0.0.0/8", "description": "cluster-egress"},
+		{"network": "192.168.0.0/16", "description": "onprem"},
+		{"network": "172.16.0.0/12", "description": "onprem"}
+	]))]
+	fn ip_filter_added_from_defaults(#[case] environment: &str, #[case] expected: Value) {
+		let config = ip_filter_config(PolicyAction::Deny);
 		let valkey = create_object(None);
 		let mut patches = Vec::new();
+		let mut warnings = Vec::new();
 
-		add_tags(&config, &valkey, &mut patches);
+		add_ip_filter(&config, environment, &valkey, &mut patches, &mut warnings).unwrap();
 
-		assert_eq!(patches.len(), 1);
-		let patch = patches.pop().unwrap();
-		match patch {
-			PatchOperation::Add(add) => {
-				assert_eq!(add.path, "/spec/tags");
-				assert_eq!(
-					add.value,
-					json!({
-						"environment": ENVIRONMENT.to_string(),
-						"team": NAMESPACE.to_string(),
-						"tenant": TENANT.to_string(),
-					}),
-					"incorrect json structure added"
-				);
-			},
-			_ => {
-				panic!("Wrong patch operation in patches");
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(valkey.user_config_path(), json!({})),
+				add_patch(valkey.ip_filter_path(), expected),
+			]
+		);
+		assert!(warnings.is_empty());
+	}
+
+	#[rstest]
+	fn ip_filter_from_annotation_is_union_of_sets() {
+		let config = ip_filter_config(PolicyAction::Deny);
+		let mut valkey = create_object(None);
+		valkey.metadata.annotations = Some(BTreeMap::from([(
+			IP_FILTER_ANNOTATION.to_string(),
+			"onprem, office".to_string(),
+		)]));
+		valkey.data["spec"]["userConfig"] = json!({"ip_filter": ["192.168.0.0/16"]});
+		let mut patches = Vec::new();
+		let mut warnings = Vec::new();
+
+		add_ip_filter(&config, "dev", &valkey, &mut patches, &mut warnings).unwrap();
+
+		let append = valkey.ip_filter_path().with_trailing_token("-");
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(
+					append.clone(),
+					json!({"network": "172.16.0.0/12", "description": "onprem"})
+				),
+				add_patch(
+					append,
+					json!({"network": "192.168.1.0/24", "description": "office"})
+				),
+			]
+		);
+	}
+
+	#[rstest]
+	fn ip_filter_with_unknown_set_is_denied() {
+		let config = ip_filter_config(PolicyAction::Warn);
+		let mut valkey = create_object(None);
+		valkey.metadata.annotations = Some(BTreeMap::from([(
+			IP_FILTER_ANNOTATION.to_string(),
+			"internet".to_string(),
+		)]));
+		let mut patches = Vec::new();
+		let mut warnings = Vec::new();
+
+		let err = add_ip_filter(&config, "dev", &valkey, &mut patches, &mut warnings).unwrap_err();
+
+		assert!(err.to_string().contains("\"internet\""));
+	}
+
+	#[rstest]
+	#[case::deny(PolicyAction::Deny)]
+	#[case::warn(PolicyAction::Warn)]
+	fn ip_filter_outside_sets(#[case] policy: PolicyAction) {
+		let config = ip_filter_config(policy);
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["userConfig"] = json!({"ip_filter": [
+			{"network": "10.1.0.0/16"},
+			{"network": "0.0.0.0/0"}
+		]});
+		let mut patches = Vec::new();
+		let mut warnings = Vec::new();
+
+		let result = add_ip_filter(&config, "dev", &valkey, &mut patches, &mut warnings);
+
+		let expected = "ip_filter entry 0.0.0.0/0 is outside the allowed network sets";
+		match policy {
+			PolicyAction::Deny => assert_eq!(result.unwrap_err().to_string(), expected),
+			PolicyAction::Warn => {
+				result.unwrap();
+				assert_eq!(warnings, vec![expected.to_string()]);
 			},
 		}
+		assert_eq!(patches, vec![]);
 	}
 
 	#[rstest]
-	fn add_tags_when_other_tags_exists(config: Arc<AppConfig>) {
-		let valkey = create_object(Some(BTreeMap::from([(
-			"cool".to_string(),
-			"tag".to_string(),
-		)])));
-		let mut patches: Vec<PatchOperation> = Vec::new();
+	#[case::dev("dev", Some("startup-4"))]
+	#[case::prod("prod", Some("business-4"))]
+	#[case::unknown_environment("test", None)]
+	fn plan_added_per_environment(#[case] environment: &str, #[case] expected: Option<&str>) {
+		let config = PlanConfig {
+			defaults: BTreeMap::from([(
+				"Valkey".to_string(),
+				BTreeMap::from([
+					("dev".to_string(), "startup-4".to_string()),
+					("prod".to_string(), "business-4".to_string()),
+				]),
+			)]),
+		};
+		let mut valkey = create_object(None);
+		valkey.data["spec"].as_object_mut().unwrap().remove("plan");
+		let mut patches = Vec::new();
 
-		add_tags(&config, &valkey, &mut patches);
-		let actual = make_comparable_set(&mut patches);
+		add_plan(&config, environment, &valkey, &mut patches);
 
-		let mut expected = BTreeSet::new();
-		for (key, value) in TAG_PAIRS {
-			expected.insert(("add", format!("/spec/tags/{}", key), value.to_string()));
-		}
-		assert_eq!(actual, expected, "contains expected patches");
+		let expected: Vec<PatchOperation> = expected
+			.map(|plan| add_patch(valkey.plan_path(), json!(plan)))
+			.into_iter()
+			.collect();
+		assert_eq!(patches, expected);
 	}
 
 	#[rstest]
-	fn replace_tags_when_wrong_values_are_set(config: Arc<AppConfig>) {
-		let mut existing_tags = BTreeMap::new();
-		for (key, _value) in TAG_PAIRS {
-			existing_tags.insert(key.to_string(), "invalid".to_string());
-		}
-		let valkey = create_object(Some(existing_tags));
-		let mut patches: Vec<PatchOperation> = Vec::new();
+	fn plan_set_by_user_is_kept() {
+		let config = PlanConfig {
+			defaults: BTreeMap::from([(
+				"*".to_string(),
+				BTreeMap::from([("*".to_string(), "startup-4".to_string())]),
+			)]),
+		};
+		let valkey = create_object(None);
+		let mut patches = Vec::new();
 
-		add_tags(&config, &valkey, &mut patches);
-		let actual = make_comparable_set(&mut patches);
+		add_plan(&config, "dev", &valkey, &mut patches);
 
-		let mut expected = BTreeSet::new();
-		for (key, value) in TAG_PAIRS {
-			expected.insert(("replace", format!("/spec/tags/{}", key), value.to_string()));
+		assert_eq!(patches, vec![]);
+	}
+
+	fn conn_info_secret_config() -> ConnInfoSecretConfig {
+		ConnInfoSecretConfig {
+			name_template: "aiven-{kind}-{name}".to_string(),
+			labels: BTreeMap::from([
+				("team".to_string(), "{team}".to_string()),
+				(
+					"app.kubernetes.io/managed-by".to_string(),
+					"aiven-operator".to_string(),
+				),
+			]),
+			annotations: BTreeMap::from([(
+				"reloader.stakater.com/match".to_string(),
+				"true".to_string(),
+			)]),
 		}
-		assert_eq!(actual, expected, "contains expected patches");
 	}
 
 	#[rstest]
-	#[case(ENVIRONMENT, TENANT)]
-	#[case(ENVIRONMENT, NAMESPACE)]
-	#[case(TENANT, NAMESPACE)]
-	#[case(TENANT, ENVIRONMENT)]
-	#[case(NAMESPACE, ENVIRONMENT)]
-	#[case(NAMESPACE, TENANT)]
-	fn add_or_replace_as_needed(
+	#[case::no_labels(None, Some(json!({
+		"team": "my-team",
+		"app.kubernetes.io/managed-by": "mutilator"
+	})))]
+	#[case::other_labels(Some(json!({"app": "my-app", "team": "other-team"})), None)]
+	fn metadata_labels(
 		config: Arc<AppConfig>,
-		#[case] correct: &str,
-		#[case] invalid: &str,
+		#[case] existing: Option<Value>,
+		#[case] expected: Option<Value>,
 	) {
-		let mut existing_tags = BTreeMap::new();
-		for (key, value) in TAG_PAIRS {
-			match value {
-				c if c == correct => {
-					existing_tags.insert(key.to_string(), correct.to_string());
-				},
-				i if i == invalid => {
-					existing_tags.insert(key.to_string(), "invalid".to_string());
-				},
-				_ => {},
-			}
-		}
-		let valkey = create_object(Some(existing_tags));
-		let mut patches: Vec<PatchOperation> = Vec::new();
+		let metadata = MetadataConfig {
+			labels: BTreeMap::from([
+				("team".to_string(), "{team}".to_string()),
+				(
+					"app.kubernetes.io/managed-by".to_string(),
+					"mutilator".to_string(),
+				),
+			]),
+		};
+		let mut valkey = create_object(None);
+		valkey.metadata.labels = existing.map(|labels| serde_json::from_value(labels).unwrap());
+		let variables = template_variables(&config, Some("my-team"), &valkey);
+		let mut patches = Vec::new();
 
-		add_tags(&config, &valkey, &mut patches);
-		let actual = make_comparable_set(&mut patches);
+		add_metadata_labels(&metadata, &variables, &valkey, &mut patches);
 
-		let mut expected = BTreeSet::new();
-		for (key, value) in TAG_PAIRS {
-			match value {
-				v if v == correct => {},
-				v if v == invalid => {
-					expected.insert(("replace", format!("/spec/tags/{}", key), value.to_string()));
-				},
-				_ => {
-					expected.insert(("add", format!("/spec/tags/{}", key), value.to_string()));
-				},
-			}
+		let expected = match expected {
+			Some(labels) => vec![add_patch(valkey.labels_path(), labels)],
+			None => vec![
+				add_patch(
+					PointerBuf::from_tokens(["metadata", "labels", "app.kubernetes.io/managed-by"]),
+					json!("mutilator"),
+				),
+				replace_patch(
+					PointerBuf::from_tokens(["metadata", "labels", "team"]),
+					json!("my-team"),
+				),
+			],
+		};
+		assert_eq!(patches, expected);
+	}
+
+	#[rstest]
+	fn metadata_labels_skip_empty_values(config: Arc<AppConfig>) {
+		let metadata = MetadataConfig {
+			labels: BTreeMap::from([("team".to_string(), "{team}".to_string())]),
+		};
+		let valkey = create_object(None);
+		let variables = template_variables(&config, None, &valkey);
+		let mut patches = Vec::new();
+
+		add_metadata_labels(&metadata, &variables, &valkey, &mut patches);
+
+		assert_eq!(patches, vec![]);
+	}
+
+	#[rstest]
+	fn conn_info_secret_target_added(config: Arc<AppConfig>) {
+		let valkey = create_object(None);
+		let variables = template_variables(&config, Some("my-team"), &valkey);
+		let mut patches = Vec::new();
+
+		add_conn_info_secret_target(
+			&conn_info_secret_config(),
+			&variables,
+			&valkey,
+			None,
+			&mut patches,
+		);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				valkey.conn_info_secret_target_path(),
+				json!({
+					"name": "aiven-valkey-test-name",
+					"labels": {
+						"team": "my-team",
+						"app.kubernetes.io/managed-by": "aiven-operator"
+					},
+					"annotations": {"reloader.stakater.com/match": "true"}
+				})
+			)]
+		);
+	}
+
+	#[rstest]
+	fn conn_info_secret_target_completed(config: Arc<AppConfig>) {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["connInfoSecretTarget"] = json!({
+			"prefix": "REDIS_",
+			"labels": {"team": "other-team", "app": "my-app"}
+		});
+		let variables = template_variables(&config, Some("my-team"), &valkey);
+		let mut patches = Vec::new();
+
+		add_conn_info_secret_target(
+			&conn_info_secret_config(),
+			&variables,
+			&valkey,
+			None,
+			&mut patches,
+		);
+
+		let path = valkey.conn_info_secret_target_path();
+		assert_eq!(
+			patches,
+			vec![
+				add_patch(
+					path.with_trailing_token("name"),
+					json!("aiven-valkey-test-name")
+				),
+				add_patch(
+					PointerBuf::from_tokens([
+						"spec",
+						"connInfoSecretTarget",
+						"labels",
+						"app.kubernetes.io/managed-by"
+					]),
+					json!("aiven-operator")
+				),
+				replace_patch(
+					PointerBuf::from_tokens(["spec", "connInfoSecretTarget", "labels", "team"]),
+					json!("my-team")
+				),
+				add_patch(
+					path.with_trailing_token("annotations"),
+					json!({"reloader.stakater.com/match": "true"})
+				),
+			]
+		);
+	}
+
+	#[rstest]
+	fn conn_info_secret_target_name_set_by_user_is_kept(config: Arc<AppConfig>) {
+		let mut valkey = create_object(None);
+		valkey.data["spec"]["connInfoSecretTarget"] = json!({"name": "my-secret"});
+		let variables = template_variables(&config, None, &valkey);
+		let mut patches = Vec::new();
+
+		add_conn_info_secret_target(
+			&ConnInfoSecretConfig::default(),
+			&variables,
+			&valkey,
+			None,
+			&mut patches,
+		);
+
+		assert_eq!(patches, vec![]);
+	}
+
+	#[rstest]
+	#[case::never_set(json!(null), "test-name")]
+	#[case::set_before(json!({"name": "my-secret"}), "my-secret")]
+	fn conn_info_secret_target_name_kept_on_update(
+		config: Arc<AppConfig>,
+		#[case] old_target: Value,
+		#[case] expected_name: &str,
+	) {
+		let valkey = create_object(None);
+		let mut old_valkey = create_object(None);
+		old_valkey.data["spec"]["connInfoSecretTarget"] = old_target;
+		let variables = template_variables(&config, Some("my-team"), &valkey);
+		let mut patches = Vec::new();
+
+		add_conn_info_secret_target(
+			&conn_info_secret_config(),
+			&variables,
+			&valkey,
+			Some(&old_valkey),
+			&mut patches,
+		);
+
+		assert_eq!(
+			patches,
+			vec![add_patch(
+				valkey.conn_info_secret_target_path(),
+				json!({
+					"name": expected_name,
+					"labels": {
+						"team": "my-team",
+						"app.kubernetes.io/managed-by": "aiven-operator"
+					},
+					"annotations": {"reloader.stakater.com/match": "true"}
+				})
+			)]
+		);
+	}
+
+	fn add_value(patch: &PatchOperation) -> &Value {
+		match patch {
+			PatchOperation::Add(add) => &add.value,
+			_ => panic!("Expected add operation, got {:?}", patch),
 		}
-		assert_eq!(actual, expected, "contains expected patches");
 	}
 
-	fn make_comparable_set(patches: &mut Vec<PatchOperation>) -> BTreeSet<(&str, String, String)> {
+	fn make_comparable_set(patches: &[PatchOperation]) -> BTreeSet<(&str, String, String)> {
 		patches
-			.clone()
-			.into_iter()
+			.iter()
+			.cloned()
 			.map(|p| match p {
 				PatchOperation::Add(add) => (
 					"add",
@@ -294,8 +3269,42 @@ mod tests {
 			.collect()
 	}
 
-	fn create_object(tags: Option<BTreeMap<String, String>>) -> Box<dyn AivenObject> {
-		let object: DynamicObject = serde_json::from_value(json!({
+	fn catalog() -> Catalog {
+		serde_json::from_value(json!({
+			"version": "test",
+			"clouds": ["google-europe-north1"],
+			"services": {
+				"PostgreSQL": {"versions": ["16", "17"]},
+				"Valkey": {"plans": ["hobbyist"]}
+			}
+		}))
+		.unwrap()
+	}
+
+	fn cluster(project_vpcs: Option<&[(&str, &str, Option<&str>)]>) -> ClusterState {
+		let (namespaces, _) = reflector::store();
+		let project_vpcs = project_vpcs.map(|vpcs| {
+			let mut writer = reflector::store::Writer::new(cluster::project_vpc_resource());
+			for (namespace, name, id) in vpcs {
+				let mut vpc =
+					DynamicObject::new(name, &cluster::project_vpc_resource()).within(namespace);
+				vpc.data = json!({"status": {"id": id}});
+				writer.apply_watcher_event(&watcher::Event::Apply(vpc));
+			}
+			writer.as_reader()
+		});
+		ClusterState {
+			namespaces,
+			project_vpcs,
+		}
+	}
+
+	fn create_object(tags: Option<BTreeMap<String, String>>) -> DynamicObject {
+		create_object_with_tags(json!(tags))
+	}
+
+	fn create_object_with_tags(tags: Value) -> DynamicObject {
+		serde_json::from_value(json!({
 			"apiVersion": "aiven.io/v1",
 			"kind": "Valkey",
 			"metadata": {
@@ -308,7 +3317,6 @@ mod tests {
 				"tags": tags
 			}
 		}))
-		.unwrap();
-		Box::new(object)
+		.unwrap()
 	}
 }
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A regular expression from the configuration, compiled once when it is loaded
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(Regex);
+
+impl Pattern {
+	/// The first capture group that matched `value`, or the whole match
+	pub fn extract(&self, value: &str) -> Option<String> {
+		let captures = self.0.captures(value)?;
+		let matched = captures
+			.iter()
+			.skip(1)
+			.flatten()
+			.next()
+			.or(captures.get(0))?;
+		Some(matched.as_str().to_string())
+	}
+}
+
+impl FromStr for Pattern {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Regex::new(s)
+			.map(Pattern)
+			.map_err(|err| format!("{:?} is not a valid pattern: {}", s, err))
+	}
+}
+
+impl TryFrom<String> for Pattern {
+	type Error = String;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl From<Pattern> for String {
+	fn from(pattern: Pattern) -> Self {
+		pattern.to_string()
+	}
+}
+
+impl fmt::Display for Pattern {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0.as_str())
+	}
+}
+
+impl PartialEq for Pattern {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.as_str() == other.0.as_str()
+	}
+}
+
+impl Eq for Pattern {}
+
+#[cfg(test)]
+mod tests {
+	use pretty_assertions::assert_eq;
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::first_group("^([^@]+)@|^(.+)$", "jane@example.com", Some("jane"))]
+	#[case::second_group("^([^@]+)@|^(.+)$", "jane", Some("jane"))]
+	#[case::whole_match("^[a-z]+", "jane@example.com", Some("jane"))]
+	#[case::no_match("^[0-9]+$", "jane", None)]
+	fn extract(#[case] pattern: &str, #[case] value: &str, #[case] expected: Option<&str>) {
+		let pattern: Pattern = pattern.parse().unwrap();
+
+		assert_eq!(pattern.extract(value).as_deref(), expected);
+	}
+
+	#[rstest]
+	fn invalid_pattern_is_rejected() {
+		assert!("^(.+@".parse::<Pattern>().is_err());
+	}
+}
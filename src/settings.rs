@@ -1,8 +1,36 @@
+use jsonptr::PointerBuf;
 use schematic::{Config, ConfigEnum, ConfigLoader};
 use serde::{Deserialize, Serialize};
-use std::{io::IsTerminal, path::PathBuf};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, fmt, io::IsTerminal, path::PathBuf, str::FromStr};
 use tracing::level_filters::LevelFilter;
 
+use crate::cidr::Cidr;
+use crate::pattern::Pattern;
+use crate::tag;
+
+const CONFIG_FILE_KEY: &str = "MUTILATOR__CONFIG_FILE";
+const UUID_PATTERN: &str = "^[a-z0-9]{8}-[a-z0-9]{4}-[a-z0-9]{4}-[a-z0-9]{4}-[a-z0-9]{12}";
+
+/// Values keyed by kind and then by environment, where `*` matches any
+pub type PerKindAndEnvironment<T> = BTreeMap<String, BTreeMap<String, T>>;
+
+/// Look up the most specific value for a kind and environment
+pub fn for_kind_and_environment<'a, T>(
+	map: &'a PerKindAndEnvironment<T>,
+	kind: &str,
+	environment: &str,
+) -> Option<&'a T> {
+	[kind, "*"]
+		.iter()
+		.filter_map(|kind| map.get(*kind))
+		.find_map(|by_environment| {
+			by_environment
+				.get(environment)
+				.or_else(|| by_environment.get("*"))
+		})
+}
+
 #[derive(ConfigEnum, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum LogFormat {
 	Plain,
@@ -28,9 +56,9 @@ pub enum LogLevel {
 	Error_ = 4,
 }
 
-impl Into<LevelFilter> for &LogLevel {
-	fn into(self) -> LevelFilter {
-		match self {
+impl From<&LogLevel> for LevelFilter {
+	fn from(level: &LogLevel) -> Self {
+		match level {
 			LogLevel::Trace => LevelFilter::TRACE,
 			LogLevel::Debug => LevelFilter::DEBUG,
 			LogLevel::Info => LevelFilter::INFO,
@@ -61,6 +89,499 @@ pub struct Tenant {
 	pub name: String,
 }
 
+#[derive(ConfigEnum, Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingTeam {
+	#[default]
+	Deny,
+	Omit,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[config(env_prefix = "MUTILATOR__TEAM__")]
+pub struct TeamConfig {
+	// Namespace label holding the team name, falling back to the namespace name
+	#[setting(default = "team", parse_env = schematic::env::ignore_empty)]
+	pub label: String,
+	// What to do when neither label nor namespace name is available
+	#[serde(default)]
+	#[setting]
+	pub on_missing: MissingTeam,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[config(env_prefix = "MUTILATOR__AUTH_SECRET__")]
+pub struct AuthSecretConfig {
+	// Secret holding the Aiven token, unless overridden by namespace annotation
+	#[setting(default = "aiven-token", parse_env = schematic::env::ignore_empty)]
+	pub name: String,
+	// Key of the token in the secret
+	#[setting(default = "token", parse_env = schematic::env::ignore_empty)]
+	pub key: String,
+	// Other secret names users may point authSecretRef at
+	#[setting(parse_env = schematic::env::split_comma)]
+	pub allowed: Vec<String>,
+}
+
+#[derive(ConfigEnum, Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+	Monday,
+	Tuesday,
+	Wednesday,
+	Thursday,
+	Friday,
+	Saturday,
+	Sunday,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[config(env_prefix = "MUTILATOR__MAINTENANCE_WINDOW__")]
+pub struct MaintenanceWindowConfig {
+	// Days a maintenance window may be placed on, empty to leave windows to Aiven
+	#[setting(
+		default = vec![Weekday::Monday, Weekday::Tuesday, Weekday::Wednesday, Weekday::Thursday],
+		parse_env = schematic::env::split_comma
+	)]
+	pub days: Vec<Weekday>,
+	// Hours (UTC) a maintenance window may start at
+	#[setting(
+		default = vec![0, 1, 2, 3, 4, 5],
+		parse_env = schematic::env::split_comma,
+		validate = validate_hours
+	)]
+	pub hours: Vec<u8>,
+}
+
+fn validate_hours<D, C>(
+	hours: &[u8],
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	match hours.iter().find(|hour| **hour > 23) {
+		Some(hour) => Err(schematic::ValidateError::new(format!(
+			"{} is not a valid hour of the day",
+			hour
+		))),
+		None => Ok(()),
+	}
+}
+
+/// A daily UTC time range written as `HH:MM-HH:MM`, possibly wrapping midnight
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeRange {
+	// Minutes after midnight, inclusive
+	pub start: u16,
+	// Minutes after midnight, exclusive
+	pub end: u16,
+}
+
+impl TimeRange {
+	pub fn minutes(&self) -> u16 {
+		(self.end + 24 * 60 - self.start) % (24 * 60)
+	}
+}
+
+impl FromStr for TimeRange {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		fn parse_time(s: &str) -> Option<u16> {
+			let (hour, minute) = s.trim().split_once(':')?;
+			let (hour, minute) = (hour.parse::<u16>().ok()?, minute.parse::<u16>().ok()?);
+			(hour < 24 && minute < 60).then_some(hour * 60 + minute)
+		}
+
+		let invalid = || format!("{:?} is not a time range on the form HH:MM-HH:MM", s);
+		let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+		let range = TimeRange {
+			start: parse_time(start).ok_or_else(invalid)?,
+			end: parse_time(end).ok_or_else(invalid)?,
+		};
+		match range.minutes() {
+			0 => Err(format!("{:?} is an empty time range", s)),
+			_ => Ok(range),
+		}
+	}
+}
+
+impl TryFrom<String> for TimeRange {
+	type Error = String;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl From<TimeRange> for String {
+	fn from(range: TimeRange) -> Self {
+		range.to_string()
+	}
+}
+
+impl fmt::Display for TimeRange {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{:02}:{:02}-{:02}:{:02}",
+			self.start / 60,
+			self.start % 60,
+			self.end / 60,
+			self.end % 60
+		)
+	}
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[config(env_prefix = "MUTILATOR__BACKUP__")]
+pub struct BackupConfig {
+	// Time ranges (UTC) to spread backups over, empty to leave backup times to Aiven
+	#[setting(
+		default = vec![TimeRange { start: 0, end: 6 * 60 }],
+		parse_env = schematic::env::split_comma
+	)]
+	pub time_ranges: Vec<TimeRange>,
+}
+
+#[derive(ConfigEnum, Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyAction {
+	Deny,
+	#[default]
+	Warn,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct IpFilterConfig {
+	// Named sets of networks (eq. cluster-egress, onprem)
+	pub sets: BTreeMap<String, Vec<Cidr>>,
+	// Names of the sets to use when ip_filter is missing, per kind and environment
+	#[setting(validate = validate_default_sets)]
+	pub defaults: PerKindAndEnvironment<Vec<String>>,
+	// What to do with ip_filter entries outside every configured set
+	#[serde(default)]
+	#[setting(env = "MUTILATOR__IP_FILTER__POLICY")]
+	pub policy: PolicyAction,
+}
+
+fn validate_default_sets<C>(
+	defaults: &PerKindAndEnvironment<Vec<String>>,
+	data: &PartialIpFilterConfig,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	let sets = data.sets.clone().unwrap_or_default();
+	let unknown = defaults
+		.values()
+		.flat_map(|by_environment| by_environment.values())
+		.flatten()
+		.find(|name| !sets.contains_key(*name));
+	match unknown {
+		Some(name) => Err(schematic::ValidateError::new(format!(
+			"unknown network set {:?}",
+			name
+		))),
+		None => Ok(()),
+	}
+}
+
+#[derive(ConfigEnum, Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CloudProvider {
+	Aws,
+	Azure,
+	Do,
+	Exoscale,
+	#[default]
+	Google,
+	Upcloud,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct CloudConfig {
+	// Cloud provider, combined with location into the Aiven cloud name (eq. google-europe-north1)
+	#[serde(default)]
+	#[setting(env = "MUTILATOR__CLOUD__PROVIDER")]
+	pub provider: CloudProvider,
+	// Full Aiven cloud name, used instead of provider and location
+	#[setting(
+		env = "MUTILATOR__CLOUD__NAME",
+		parse_env = schematic::env::ignore_empty,
+		validate = validate_cloud_name
+	)]
+	pub name: Option<String>,
+	// Cloud names per kind
+	#[setting(validate = validate_cloud_names)]
+	pub kinds: BTreeMap<String, String>,
+	// Cloud names per namespace, taking precedence over kinds
+	#[setting(validate = validate_cloud_names)]
+	pub namespaces: BTreeMap<String, String>,
+	// Locations namespaces may select by annotation, and users may put services in.
+	// When empty, user cloud names are overwritten
+	#[setting(
+		env = "MUTILATOR__CLOUD__ALLOWED_LOCATIONS",
+		parse_env = schematic::env::split_comma,
+		validate = validate_locations
+	)]
+	pub allowed_locations: Vec<String>,
+}
+
+impl CloudConfig {
+	/// Cloud names of the allowed locations
+	pub fn allowed_cloud_names(&self) -> Vec<String> {
+		self.allowed_locations
+			.iter()
+			.map(|location| format!("{}-{}", self.provider, location))
+			.collect()
+	}
+}
+
+fn validate_locations<C>(
+	locations: &[String],
+	data: &PartialCloudConfig,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	let provider = data.provider.unwrap_or_default();
+	locations
+		.iter()
+		.try_for_each(|location| check_cloud_name(&format!("{}-{}", provider, location)))
+}
+
+fn check_cloud_name(name: &str) -> Result<(), schematic::ValidateError> {
+	let providers = CloudProvider::variants()
+		.iter()
+		.map(|provider| provider.to_string())
+		.collect::<Vec<_>>()
+		.join("|");
+	let pattern = regex::Regex::new(&format!("^({})-[a-z0-9]+(-[a-z0-9]+)*$", providers))
+		.map_err(|err| schematic::ValidateError::new(err.to_string()))?;
+	match pattern.is_match(name) {
+		true => Ok(()),
+		false => Err(schematic::ValidateError::new(format!(
+			"{:?} is not a valid Aiven cloud name",
+			name
+		))),
+	}
+}
+
+fn validate_cloud_name<D, C>(
+	name: &str,
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	check_cloud_name(name)
+}
+
+fn validate_cloud_names<D, C>(
+	names: &BTreeMap<String, String>,
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	names.values().try_for_each(|name| check_cloud_name(name))
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct VpcConfig {
+	// Project VPC IDs per cloud name, besides project_vpc_id for the default cloud
+	#[setting(validate = validate_vpc_ids)]
+	pub ids: BTreeMap<String, String>,
+	// Look up projectVPCRef targets among the ProjectVPC resources in the cluster
+	pub resolve_refs: bool,
+}
+
+fn validate_vpc_ids<D, C>(
+	ids: &BTreeMap<String, String>,
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	let uuid = regex::Regex::new(UUID_PATTERN)
+		.map_err(|err| schematic::ValidateError::new(err.to_string()))?;
+	for (cloud_name, id) in ids {
+		check_cloud_name(cloud_name)?;
+		if !uuid.is_match(id) {
+			return Err(schematic::ValidateError::new(format!(
+				"{:?} is not a valid project VPC ID",
+				id
+			)));
+		}
+	}
+	Ok(())
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct PlanConfig {
+	// Plan to use when spec.plan is missing, per kind and environment
+	pub defaults: PerKindAndEnvironment<String>,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct VersionConfig {
+	// Recommended version per kind, used when the version in userConfig is missing
+	pub defaults: BTreeMap<String, String>,
+	// Versions each version may be changed to, per kind. Kinds without an entry may change freely
+	pub upgrades: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldAction {
+	#[default]
+	Remove,
+	Deny,
+}
+
+/// A field users may not set, as a JSON pointer into the object (eq. /spec/userConfig/static_ips)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ForbiddenField {
+	pub pointer: PointerBuf,
+	#[serde(default)]
+	pub action: FieldAction,
+	// Explanation given when denying
+	#[serde(default)]
+	pub reason: Option<String>,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct FieldsConfig {
+	// Fields to remove or deny, per kind, where `*` matches any kind
+	pub forbidden: BTreeMap<String, Vec<ForbiddenField>>,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct UserConfigConfig {
+	// Fragments deep-merged into spec.userConfig per kind, only filling keys the user didn't set
+	pub defaults: BTreeMap<String, Map<String, Value>>,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct TagsConfig {
+	// Tags added besides environment, tenant and team, values may use placeholders
+	#[setting(validate = validate_extra_tags)]
+	pub extra: BTreeMap<String, String>,
+	// Namespace labels copied to tags, keyed by label and giving the tag name
+	#[setting(validate = validate_mapped_tags)]
+	pub namespace_labels: BTreeMap<String, String>,
+	// Namespace annotations copied to tags, keyed by annotation and giving the tag name
+	#[setting(validate = validate_mapped_tags)]
+	pub namespace_annotations: BTreeMap<String, String>,
+	// Tag keys owned by mutilator besides environment, tenant and team
+	pub reserved: Vec<String>,
+	// Tag key prefixes owned by mutilator
+	pub reserved_prefixes: Vec<String>,
+	// Rules turning object labels and annotations into tags, the first matching rule per tag wins
+	#[setting(validate = validate_source_tags)]
+	pub sources: Vec<SourceTagRule>,
+}
+
+/// Tag taken from a label or annotation on the object, eq. from GitOps tooling
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct SourceTagRule {
+	pub tag: String,
+	#[serde(default)]
+	pub label: Option<String>,
+	#[serde(default)]
+	pub annotation: Option<String>,
+	// Regex extracting the value, using the first capture group that matched, if any
+	#[serde(default)]
+	pub pattern: Option<Pattern>,
+	// Fixed tag value to use when the rule matches
+	#[serde(default)]
+	pub value: Option<String>,
+}
+
+fn validate_source_tags<D, C>(
+	rules: &[SourceTagRule],
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	for rule in rules {
+		if let Some(problem) = tag::check_key(&rule.tag) {
+			return Err(schematic::ValidateError::new(problem));
+		}
+		if rule.label.is_some() == rule.annotation.is_some() {
+			return Err(schematic::ValidateError::new(format!(
+				"source tag {} needs exactly one of label or annotation",
+				rule.tag
+			)));
+		}
+	}
+	Ok(())
+}
+
+fn validate_extra_tags<D, C>(
+	tags: &BTreeMap<String, String>,
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	match tags.keys().find_map(|key| tag::check_key(key)) {
+		Some(problem) => Err(schematic::ValidateError::new(problem)),
+		None => Ok(()),
+	}
+}
+
+fn validate_mapped_tags<D, C>(
+	mapping: &BTreeMap<String, String>,
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	match mapping.values().find_map(|key| tag::check_key(key)) {
+		Some(problem) => Err(schematic::ValidateError::new(problem)),
+		None => Ok(()),
+	}
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[config(env_prefix = "MUTILATOR__CREATED_BY__")]
+pub struct CreatedByConfig {
+	// Tag recording who created the resource
+	#[setting(default = "created-by", parse_env = schematic::env::ignore_empty, validate = validate_tag_key)]
+	pub tag: String,
+	// Annotation recording who created the resource
+	#[setting(default = "mutilator.nais.io/created-by", parse_env = schematic::env::ignore_empty)]
+	pub annotation: String,
+	// Regex mapping usernames to a short identity, using the first capture group that matched, if any
+	#[setting(parse_env = schematic::env::ignore_empty)]
+	pub pattern: Option<Pattern>,
+}
+
+fn validate_tag_key<D, C>(
+	key: &str,
+	_data: &D,
+	_context: &C,
+	_finalize: bool,
+) -> Result<(), schematic::ValidateError> {
+	match tag::check_key(key) {
+		Some(problem) => Err(schematic::ValidateError::new(problem)),
+		None => Ok(()),
+	}
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct MetadataConfig {
+	// Labels to put on the Aiven resources, empty values are left out
+	pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Config, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ConnInfoSecretConfig {
+	// Name of the secret when connInfoSecretTarget.name is missing
+	#[setting(default = "aiven-{kind}-{name}")]
+	pub name_template: String,
+	// Labels to put on the secret
+	pub labels: BTreeMap<String, String>,
+	// Annotations to put on the secret
+	pub annotations: BTreeMap<String, String>,
+}
+
 #[derive(Config, Debug, Deserialize, Serialize, Clone)]
 #[config(env_prefix = "MUTILATOR__")]
 pub struct AppConfig {
@@ -78,17 +599,93 @@ pub struct AppConfig {
 	// Tenant details
 	#[setting(nested)]
 	pub tenant: Tenant,
+	// Team resolution
+	#[setting(nested)]
+	pub team: TeamConfig,
+	// Secret with the Aiven token
+	#[setting(nested)]
+	pub auth_secret: AuthSecretConfig,
+	// Spreading of maintenance windows
+	#[setting(nested)]
+	pub maintenance_window: MaintenanceWindowConfig,
+	// Spreading of backup times
+	#[setting(nested)]
+	pub backup: BackupConfig,
+	// Network sets for userConfig.ip_filter
+	#[setting(nested)]
+	pub ip_filter: IpFilterConfig,
+	// Default plans
+	#[setting(nested)]
+	pub plan: PlanConfig,
+	// Service versions
+	#[setting(nested)]
+	pub version: VersionConfig,
+	// Fields users may not set
+	#[setting(nested)]
+	pub fields: FieldsConfig,
+	// Default userConfig values
+	#[setting(nested)]
+	pub user_config: UserConfigConfig,
+	// Extra tags, values may use the same placeholders as conn_info_secret
+	#[setting(nested)]
+	pub tags: TagsConfig,
+	// Recording of who created the resource
+	#[setting(nested)]
+	pub created_by: CreatedByConfig,
+	// Metadata of the Aiven resources, values may use the same placeholders as conn_info_secret
+	#[setting(nested)]
+	pub metadata: MetadataConfig,
+	// Naming and metadata of connection info secrets, values may use the
+	// {kind}, {name}, {namespace}, {team}, {tenant} and {environment} placeholders
+	#[setting(nested)]
+	pub conn_info_secret: ConnInfoSecretConfig,
 	// Aiven VPC ID
-	#[setting(validate = schematic::validate::regex("^[a-z0-9]{8}-[a-z0-9]{4}-[a-z0-9]{4}-[a-z0-9]{4}-[a-z0-9]{12}"))]
+	#[setting(validate = schematic::validate::regex(UUID_PATTERN))]
 	pub project_vpc_id: String,
+	// Project VPCs of other clouds
+	#[setting(nested)]
+	pub vpc: VpcConfig,
 	// Cloud location (eq. europe-north1)
-	#[setting(default = "europe-north1", parse_env = schematic::env::ignore_empty)]
+	#[setting(
+		default = "europe-north1",
+		parse_env = schematic::env::ignore_empty,
+		validate = schematic::validate::regex("^[a-z0-9]+(-[a-z0-9]+)*$")
+	)]
 	pub location: String,
+	// Cloud provider and overrides of the cloud name
+	#[setting(nested)]
+	pub cloud: CloudConfig,
+	// Catalog of clouds, plans and versions, replacing the bundled one
+	pub catalog_path: Option<PathBuf>,
 	// Enabled OpenTelemetry collector
 	#[setting(default = false, env = "OTEL_EXPORTER_OTLP_ENDPOINT", parse_env = parse_otel)]
 	pub otel_enabled: bool,
 }
 
+impl AppConfig {
+	/// Cloud name used when there are no overrides
+	pub fn default_cloud_name(&self) -> String {
+		self.cloud
+			.name
+			.clone()
+			.unwrap_or_else(|| format!("{}-{}", self.cloud.provider, self.location))
+	}
+
+	/// Project VPC ID for a cloud name, if one is configured
+	pub fn project_vpc_id_for(&self, cloud_name: &str) -> Option<&str> {
+		match self.vpc.ids.get(cloud_name) {
+			Some(id) => Some(id),
+			None if cloud_name == self.default_cloud_name() => Some(&self.project_vpc_id),
+			None => None,
+		}
+	}
+
+	/// Whether a project VPC ID is one of the VPCs configured for this tenant
+	pub fn is_tenant_project_vpc(&self, id: &str) -> bool {
+		id == self.project_vpc_id || self.vpc.ids.values().any(|known| known == id)
+	}
+}
+
 pub fn parse_otel(var: String) -> Result<Option<bool>, schematic::HandlerError> {
 	let var = var.trim();
 
@@ -100,7 +697,11 @@ pub fn parse_otel(var: String) -> Result<Option<bool>, schematic::HandlerError>
 }
 
 pub fn load_config() -> anyhow::Result<AppConfig> {
-	let config_load_result = ConfigLoader::<AppConfig>::new().load()?;
+	let mut loader = ConfigLoader::<AppConfig>::new();
+	if let Some(path) = std::env::var_os(CONFIG_FILE_KEY).filter(|path| !path.is_empty()) {
+		loader.file(PathBuf::from(path))?;
+	}
+	let config_load_result = loader.load()?;
 	Ok(config_load_result.config)
 }
 
@@ -113,18 +714,25 @@ mod tests {
 	use rstest::*;
 	use std::ffi::OsString;
 
-	const BIND_ADDRESS: &'static str = "127.0.0.1:9443";
-	const BIND_ADDRESS_KEY: &'static str = "MUTILATOR__WEB__BIND_ADDRESS";
-	const LOCATION: &'static str = "my-location";
-	const LOCATION_KEY: &'static str = "MUTILATOR__LOCATION";
-	const PROJECT_VPC_ID: &'static str = "ba5eba11-dead-bea7-babe-decea5edbabe";
-	const PROJECT_VPC_ID_KEY: &'static str = "MUTILATOR__PROJECT_VPC_ID";
+	const BIND_ADDRESS: &str = "127.0.0.1:9443";
+	const BIND_ADDRESS_KEY: &str = "MUTILATOR__WEB__BIND_ADDRESS";
+	const LOCATION: &str = "my-location";
+	const LOCATION_KEY: &str = "MUTILATOR__LOCATION";
+	const PROJECT_VPC_ID: &str = "ba5eba11-dead-bea7-babe-decea5edbabe";
+	const PROJECT_VPC_ID_KEY: &str = "MUTILATOR__PROJECT_VPC_ID";
+	const TEAM_LABEL: &str = "nais.io/team";
+	const TEAM_LABEL_KEY: &str = "MUTILATOR__TEAM__LABEL";
+	const CATALOG_PATH: &str = "/etc/mutilator/catalog.json";
+	const CATALOG_PATH_KEY: &str = "MUTILATOR__CATALOG_PATH";
 
 	#[rstest]
 	#[case::bind_address(BIND_ADDRESS_KEY, BIND_ADDRESS, BIND_ADDRESS)]
 	#[case::location_set(LOCATION_KEY, LOCATION, LOCATION)]
 	#[case::location_blank(LOCATION_KEY, "europe-north1", "")]
 	#[case::project_vpc_id(PROJECT_VPC_ID_KEY, PROJECT_VPC_ID, PROJECT_VPC_ID)]
+	#[case::team_label_set(TEAM_LABEL_KEY, TEAM_LABEL, TEAM_LABEL)]
+	#[case::team_label_blank(TEAM_LABEL_KEY, "team", "")]
+	#[case::catalog_path(CATALOG_PATH_KEY, CATALOG_PATH, CATALOG_PATH)]
 	pub fn test_load_config(#[case] key: &str, #[case] expected: &str, #[case] value: &str) {
 		let _lock = lock_test();
 		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
@@ -142,12 +750,437 @@ mod tests {
 			BIND_ADDRESS_KEY => {
 				assert_eq!(config.web.bind_address, expected)
 			},
+			TEAM_LABEL_KEY => {
+				assert_eq!(config.team.label, expected)
+			},
+			CATALOG_PATH_KEY => {
+				assert_eq!(config.catalog_path, Some(PathBuf::from(expected)))
+			},
 			_ => {
 				panic!("Unmatched configuration key in test")
 			},
 		}
 	}
 
+	#[rstest]
+	#[case::deny("deny", MissingTeam::Deny)]
+	#[case::omit("omit", MissingTeam::Omit)]
+	pub fn test_team_on_missing(#[case] value: &str, #[case] expected: MissingTeam) {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(OsString::from("MUTILATOR__TEAM__ON_MISSING"), value);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(config.team.on_missing, expected)
+	}
+
+	#[rstest]
+	pub fn test_maintenance_window() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _days_guard = set_env(
+			OsString::from("MUTILATOR__MAINTENANCE_WINDOW__DAYS"),
+			"saturday,sunday",
+		);
+		let _hours_guard = set_env(
+			OsString::from("MUTILATOR__MAINTENANCE_WINDOW__HOURS"),
+			"22,23",
+		);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(
+			config.maintenance_window,
+			MaintenanceWindowConfig {
+				days: vec![Weekday::Saturday, Weekday::Sunday],
+				hours: vec![22, 23],
+			}
+		)
+	}
+
+	#[rstest]
+	#[should_panic]
+	pub fn test_invalid_extra_tag_key() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"invalid-tag",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+tags:
+  namespaceLabels:
+    nais.io/cost-center: nais.io/cost-center
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let _config = load_config().unwrap();
+	}
+
+	#[rstest]
+	pub fn test_created_by_pattern_is_compiled() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(
+			OsString::from("MUTILATOR__CREATED_BY__PATTERN"),
+			"^([^@]+)@",
+		);
+
+		let config = load_config().unwrap();
+
+		let pattern = config.created_by.pattern.unwrap();
+		assert_eq!(pattern.extract("jane@example.com").as_deref(), Some("jane"));
+	}
+
+	#[rstest]
+	#[should_panic]
+	pub fn test_invalid_created_by_pattern() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(OsString::from("MUTILATOR__CREATED_BY__PATTERN"), "^(.+@");
+
+		let _config = load_config().unwrap();
+	}
+
+	#[rstest]
+	#[should_panic]
+	pub fn test_source_tag_needs_label_or_annotation() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"source-tag",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+tags:
+  sources:
+    - tag: source
+      value: argocd
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let _config = load_config().unwrap();
+	}
+
+	#[rstest]
+	pub fn test_cloud_provider() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(OsString::from("MUTILATOR__CLOUD__PROVIDER"), "upcloud");
+
+		let config = load_config().unwrap();
+
+		assert_eq!(config.cloud.provider, CloudProvider::Upcloud);
+	}
+
+	#[rstest]
+	#[case::valid("aws-eu-north-1", true)]
+	#[case::do_provider("do-ams", true)]
+	#[case::unknown_provider("gcp-europe-north1", false)]
+	#[case::no_region("google-", false)]
+	#[case::uppercase("google-Europe-North1", false)]
+	pub fn test_cloud_name(#[case] name: &str, #[case] valid: bool) {
+		assert_eq!(check_cloud_name(name).is_ok(), valid);
+	}
+
+	#[rstest]
+	pub fn test_allowed_locations() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(
+			OsString::from("MUTILATOR__CLOUD__ALLOWED_LOCATIONS"),
+			"europe-north1,europe-west1",
+		);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(
+			config.cloud.allowed_cloud_names(),
+			vec!["google-europe-north1", "google-europe-west1"]
+		);
+	}
+
+	#[rstest]
+	pub fn test_allowed_locations_use_provider() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _provider_guard = set_env(OsString::from("MUTILATOR__CLOUD__PROVIDER"), "aws");
+		let _guard = set_env(
+			OsString::from("MUTILATOR__CLOUD__ALLOWED_LOCATIONS"),
+			"eu-north-1",
+		);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(config.cloud.allowed_cloud_names(), vec!["aws-eu-north-1"]);
+	}
+
+	#[rstest]
+	pub fn test_project_vpc_ids_from_config_file() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"vpc",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+location: europe-north1
+vpc:
+  ids:
+    google-europe-west1: 0ddba11d-cafe-f00d-beef-5ca1ab1e0000
+  resolveRefs: true
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let config = load_config().unwrap();
+
+		assert!(config.vpc.resolve_refs);
+		assert!(config.is_tenant_project_vpc("0ddba11d-cafe-f00d-beef-5ca1ab1e0000"));
+		assert!(!config.is_tenant_project_vpc("5ca1ab1e-0000-0000-0000-000000000000"));
+
+		assert_eq!(
+			config.project_vpc_id_for("google-europe-north1"),
+			Some(PROJECT_VPC_ID)
+		);
+		assert_eq!(
+			config.project_vpc_id_for("google-europe-west1"),
+			Some("0ddba11d-cafe-f00d-beef-5ca1ab1e0000")
+		);
+		assert_eq!(config.project_vpc_id_for("aws-eu-north-1"), None);
+	}
+
+	#[rstest]
+	#[should_panic]
+	pub fn test_invalid_cloud_name_override() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"cloud-name",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+cloud:
+  kinds:
+    OpenSearch: europe-west1
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let _config = load_config().unwrap();
+	}
+
+	#[rstest]
+	pub fn test_auth_secret() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _name_guard = set_env(OsString::from("MUTILATOR__AUTH_SECRET__NAME"), "");
+		let _allowed_guard = set_env(
+			OsString::from("MUTILATOR__AUTH_SECRET__ALLOWED"),
+			"legacy-token,other-token",
+		);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(
+			config.auth_secret,
+			AuthSecretConfig {
+				name: "aiven-token".to_string(),
+				key: "token".to_string(),
+				allowed: vec!["legacy-token".to_string(), "other-token".to_string()],
+			}
+		)
+	}
+
+	#[rstest]
+	#[case::single("01:00-03:30", vec![TimeRange { start: 60, end: 210 }])]
+	#[case::wrapping("22:00-02:00", vec![TimeRange { start: 1320, end: 120 }])]
+	#[case::multiple(
+		"01:00-02:00,13:15-13:45",
+		vec![TimeRange { start: 60, end: 120 }, TimeRange { start: 795, end: 825 }]
+	)]
+	pub fn test_backup_time_ranges(#[case] value: &str, #[case] expected: Vec<TimeRange>) {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(OsString::from("MUTILATOR__BACKUP__TIME_RANGES"), value);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(config.backup.time_ranges, expected)
+	}
+
+	#[rstest]
+	#[case::empty("01:00-01:00")]
+	#[case::hour("24:00-01:00")]
+	#[case::minute("01:60-02:00")]
+	#[case::format("01:00")]
+	pub fn test_invalid_time_range(#[case] value: &str) {
+		assert!(value.parse::<TimeRange>().is_err())
+	}
+
+	#[rstest]
+	#[should_panic]
+	pub fn test_invalid_maintenance_window_hours() {
+		let _lock = lock_test();
+		let _vpc_guard = set_env(OsString::from(PROJECT_VPC_ID_KEY), PROJECT_VPC_ID);
+		let _guard = set_env(OsString::from("MUTILATOR__MAINTENANCE_WINDOW__HOURS"), "24");
+
+		let _config = load_config().unwrap();
+	}
+
+	fn write_config_file(name: &str, contents: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("mutilator-{}.yaml", name));
+		std::fs::write(&path, contents).unwrap();
+		path
+	}
+
+	#[rstest]
+	pub fn test_ip_filter_from_config_file() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"ip-filter",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+ipFilter:
+  sets:
+    cluster-egress: ["10.0.0.0/8"]
+    onprem: ["192.168.0.0/16", "172.16.0.1"]
+  defaults:
+    "*":
+      "*": [cluster-egress]
+  policy: deny
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(config.ip_filter.policy, PolicyAction::Deny);
+		assert_eq!(
+			config.ip_filter.sets["onprem"],
+			vec![
+				"192.168.0.0/16".parse::<Cidr>().unwrap(),
+				"172.16.0.1/32".parse::<Cidr>().unwrap()
+			]
+		);
+		assert_eq!(
+			for_kind_and_environment(&config.ip_filter.defaults, "Valkey", "dev"),
+			Some(&vec!["cluster-egress".to_string()])
+		);
+	}
+
+	#[rstest]
+	#[should_panic]
+	pub fn test_ip_filter_with_unknown_default_set() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"unknown-set",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+ipFilter:
+  sets:
+    cluster-egress: ["10.0.0.0/8"]
+  defaults:
+    Valkey:
+      "*": [onprem]
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let _config = load_config().unwrap();
+	}
+
+	#[rstest]
+	pub fn test_forbidden_fields_from_config_file() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"forbidden-fields",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+fields:
+  forbidden:
+    "*":
+      - pointer: /spec/userConfig/public_access
+    PostgreSQL:
+      - pointer: /spec/userConfig/static_ips
+        action: deny
+        reason: static IPs are not allowed
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(
+			config.fields.forbidden,
+			BTreeMap::from([
+				(
+					"*".to_string(),
+					vec![ForbiddenField {
+						pointer: PointerBuf::from_tokens(["spec", "userConfig", "public_access"]),
+						action: FieldAction::Remove,
+						reason: None,
+					}]
+				),
+				(
+					"PostgreSQL".to_string(),
+					vec![ForbiddenField {
+						pointer: PointerBuf::from_tokens(["spec", "userConfig", "static_ips"]),
+						action: FieldAction::Deny,
+						reason: Some("static IPs are not allowed".to_string()),
+					}]
+				),
+			])
+		);
+	}
+
+	#[rstest]
+	pub fn test_user_config_defaults_from_config_file() {
+		let _lock = lock_test();
+		let path = write_config_file(
+			"user-config",
+			r#"
+projectVpcId: ba5eba11-dead-bea7-babe-decea5edbabe
+userConfig:
+  defaults:
+    Valkey:
+      valkey_maxmemory_policy: allkeys-lru
+      migration:
+        ssl: true
+"#,
+		);
+		let _guard = set_env(OsString::from(CONFIG_FILE_KEY), &path);
+
+		let config = load_config().unwrap();
+
+		assert_eq!(
+			Value::Object(config.user_config.defaults["Valkey"].clone()),
+			serde_json::json!({
+				"valkey_maxmemory_policy": "allkeys-lru",
+				"migration": {"ssl": true}
+			})
+		);
+	}
+
+	#[rstest]
+	#[case::kind_and_environment("Valkey", "prod", Some(1))]
+	#[case::kind_any_environment("Valkey", "dev", Some(2))]
+	#[case::any_kind_and_environment("OpenSearch", "prod", Some(3))]
+	#[case::nothing("OpenSearch", "dev", None)]
+	pub fn test_for_kind_and_environment(
+		#[case] kind: &str,
+		#[case] environment: &str,
+		#[case] expected: Option<u8>,
+	) {
+		let map: PerKindAndEnvironment<u8> = BTreeMap::from([
+			(
+				"Valkey".to_string(),
+				BTreeMap::from([("prod".to_string(), 1), ("*".to_string(), 2)]),
+			),
+			("*".to_string(), BTreeMap::from([("prod".to_string(), 3)])),
+		]);
+
+		assert_eq!(
+			for_kind_and_environment(&map, kind, environment).copied(),
+			expected
+		);
+	}
+
 	#[rstest]
 	#[should_panic]
 	pub fn test_required_fields() {
@@ -0,0 +1,116 @@
+use crate::hash::stable_hash;
+
+/// Longest tag key or value Aiven accepts
+pub const MAX_LENGTH: usize = 64;
+
+fn is_key_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn is_value_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || " _=.:/@+-".contains(c)
+}
+
+/// Explain why a tag key would be rejected by Aiven, if it would
+pub fn check_key(key: &str) -> Option<String> {
+	if key.is_empty() || key.len() > MAX_LENGTH {
+		return Some(format!(
+			"tag key {:?} must be between 1 and {} characters",
+			key, MAX_LENGTH
+		));
+	}
+	if !key.starts_with(|c: char| c.is_ascii_alphabetic()) {
+		return Some(format!("tag key {:?} must start with a letter", key));
+	}
+	if !key.chars().all(is_key_char) {
+		return Some(format!(
+			"tag key {:?} may only contain letters, digits, '_' and '-'",
+			key
+		));
+	}
+	None
+}
+
+/// Explain why a tag value would be rejected by Aiven, if it would
+pub fn check_value(key: &str, value: &str) -> Option<String> {
+	if value.len() > MAX_LENGTH {
+		return Some(format!(
+			"value of tag {} may be at most {} characters",
+			key, MAX_LENGTH
+		));
+	}
+	if !value.chars().all(is_value_char) {
+		return Some(format!(
+			"value of tag {} may only contain letters, digits, spaces and any of '_=.:/@+-'",
+			key
+		));
+	}
+	None
+}
+
+/// Make a value acceptable to Aiven: lowercased, invalid characters replaced with `-`,
+/// and values too long truncated with a hash of the original value appended
+pub fn sanitize_value(value: &str) -> String {
+	let sanitized: String = value
+		.to_lowercase()
+		.chars()
+		.map(|c| if is_value_char(c) { c } else { '-' })
+		.collect();
+	if sanitized.len() <= MAX_LENGTH {
+		return sanitized;
+	}
+	let suffix = format!("-{:08x}", stable_hash(value) as u32);
+	let prefix: String = sanitized.chars().take(MAX_LENGTH - suffix.len()).collect();
+	format!("{}{}", prefix, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+	use pretty_assertions::assert_eq;
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::simple("cost-center", true)]
+	#[case::underscore("product_area", true)]
+	#[case::empty("", false)]
+	#[case::leading_digit("1team", false)]
+	#[case::slash("nais.io/team", false)]
+	#[case::too_long(&"a".repeat(65), false)]
+	fn check_tag_key(#[case] key: &str, #[case] valid: bool) {
+		assert_eq!(check_key(key).is_none(), valid);
+	}
+
+	#[rstest]
+	#[case::simple("basseng", true)]
+	#[case::empty("", true)]
+	#[case::punctuation("git@github.com:nais/mutilator", true)]
+	#[case::newline("two\nlines", false)]
+	#[case::unicode("blåbær", false)]
+	#[case::too_long(&"a".repeat(65), false)]
+	fn check_tag_value(#[case] value: &str, #[case] valid: bool) {
+		assert_eq!(check_value("key", value).is_none(), valid);
+	}
+
+	#[rstest]
+	#[case::unchanged("basseng", "basseng")]
+	#[case::lowercased("Basseng", "basseng")]
+	#[case::invalid_characters("blåbær!", "bl-b-r-")]
+	fn sanitize(#[case] value: &str, #[case] expected: &str) {
+		assert_eq!(sanitize_value(value), expected);
+	}
+
+	#[rstest]
+	fn sanitize_truncates_with_hash() {
+		let long = "a".repeat(100);
+		let other = format!("{}b", "a".repeat(99));
+
+		let sanitized = sanitize_value(&long);
+
+		assert_eq!(sanitized.len(), MAX_LENGTH);
+		assert!(check_value("key", &sanitized).is_none());
+		assert_ne!(sanitized, sanitize_value(&other));
+		assert_eq!(sanitized, sanitize_value(&long));
+	}
+}
@@ -7,30 +7,47 @@ use axum::routing::{get, post};
 use axum::{debug_handler, Router};
 use axum_server::tls_rustls::RustlsConfig;
 use json_patch::Patch;
+use kube::api::ObjectMeta;
 use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
 use kube::core::DynamicObject;
 use kube::ResourceExt;
 use tracing::{debug, error, info, info_span, instrument, warn};
 
 use crate::aiven_object::AivenObject;
+use crate::catalog::Catalog;
+use crate::cluster::ClusterState;
 use crate::mutators;
 use crate::settings::AppConfig;
 
-const ALLOWED_KINDS: [&str; 2] = ["OpenSearch", "Valkey"];
+// Kinds every mutator runs for, matching the webhook rules in the chart
+const ALLOWED_KINDS: [&str; 4] = ["MySQL", "OpenSearch", "PostgreSQL", "Valkey"];
+
+#[derive(Clone)]
+pub struct AppState {
+	pub config: Arc<AppConfig>,
+	pub cluster: ClusterState,
+	pub catalog: Arc<Catalog>,
+}
 
 #[instrument(skip_all)]
-pub async fn start_web_server(config: AppConfig) -> Result<()> {
+pub async fn start_web_server(
+	config: AppConfig,
+	cluster: ClusterState,
+	catalog: Catalog,
+) -> Result<()> {
 	let certificate_path = config.web.certificate_path.clone();
 	let private_key_path = config.web.private_key_path.clone();
 	let addr = config.web.bind_address.parse().unwrap();
 
-	let state = Arc::new(config);
+	let state = AppState {
+		config: Arc::new(config),
+		cluster,
+		catalog: Arc::new(catalog),
+	};
 	let router = create_router(state);
 
-	if certificate_path.is_some() && private_key_path.is_some() {
-		let tls_config =
-			RustlsConfig::from_pem_file(certificate_path.unwrap(), private_key_path.unwrap())
-				.await?;
+	if let (Some(certificate_path), Some(private_key_path)) = (certificate_path, private_key_path) {
+		let tls_config = RustlsConfig::from_pem_file(certificate_path, private_key_path).await?;
 		info!("Starting webserver on {} using https", addr);
 		axum_server::bind_rustls(addr, tls_config)
 			.serve(router.into_make_service())
@@ -45,19 +62,18 @@ pub async fn start_web_server(config: AppConfig) -> Result<()> {
 	Ok(())
 }
 
-fn create_router(state: Arc<AppConfig>) -> Router {
-	let router = Router::new()
+fn create_router(state: AppState) -> Router {
+	Router::new()
 		.route("/is_alive", get(|| async { "I'm alive!" }))
 		.route("/is_ready", get(|| async { "Ready for action!" }))
 		.route("/mutate", post(mutate_handler))
-		.with_state(state);
-	router
+		.with_state(state)
 }
 
 #[debug_handler]
 #[instrument(skip_all)]
 async fn mutate_handler(
-	State(config): State<Arc<AppConfig>>,
+	State(state): State<AppState>,
 	Json(admission_review): Json<AdmissionReview<DynamicObject>>,
 ) -> (StatusCode, Json<AdmissionReview<DynamicObject>>) {
 	let req: AdmissionRequest<DynamicObject> = match admission_review.try_into() {
@@ -85,7 +101,7 @@ async fn mutate_handler(
 
 	if let Some(obj) = &req.object {
 		let name = obj.name_any();
-		let namespace = obj.namespace().unwrap();
+		let namespace = obj.namespace().unwrap_or_default();
 
 		if !ALLOWED_KINDS.contains(&req.kind.kind.as_str()) {
 			debug!("Ignoring resource of kind {:?}", req.kind);
@@ -101,7 +117,20 @@ async fn mutate_handler(
 		let _resource_guard = resource_span.enter();
 		info!("Processing {} resource", req.kind.kind);
 
-		res = match mutate(res.clone(), Box::new(obj.to_owned()), &config) {
+		let namespace_meta = state.cluster.namespace(&namespace);
+		if namespace_meta.is_none() {
+			debug!("Namespace {} not found in cache", namespace);
+		}
+
+		let old_obj = req.old_object.as_ref().map(|o| o as &dyn AivenObject);
+		res = match mutate(
+			res.clone(),
+			obj,
+			old_obj,
+			namespace_meta.as_ref(),
+			req.user_info.username.as_deref(),
+			&state,
+		) {
 			Ok(res) => {
 				info!("Processing complete");
 				res
@@ -121,17 +150,86 @@ async fn mutate_handler(
 #[instrument(skip_all)]
 fn mutate(
 	res: AdmissionResponse,
-	obj: Box<dyn AivenObject>,
-	config: &Arc<AppConfig>,
+	obj: &DynamicObject,
+	old_obj: Option<&dyn AivenObject>,
+	namespace: Option<&ObjectMeta>,
+	username: Option<&str>,
+	state: &AppState,
 ) -> Result<AdmissionResponse> {
+	let config = &state.config;
+	let cluster = &state.cluster;
 	let mut patches = Vec::new();
+	let mut warnings = Vec::new();
+	let stripped = mutators::remove_forbidden_fields(&config.fields, obj, &mut patches)?;
+	let obj: &dyn AivenObject = stripped.as_ref();
+	let team = mutators::resolve_team(&config.team, obj, namespace)?;
+	let plan = mutators::add_plan(&config.plan, &config.tenant.environment, obj, &mut patches);
+	mutators::add_auth_secret_ref(&config.auth_secret, obj, namespace, &mut patches)?;
+	mutators::add_termination_protection(obj, &mut patches);
+	mutators::normalize_tags(obj, &mut patches)?;
+	let variables = mutators::template_variables(config, team.as_deref(), obj);
+	mutators::add_metadata_labels(&config.metadata, &variables, obj, &mut patches);
+	let created_by = mutators::resolve_created_by(&config.created_by, username, old_obj);
+	mutators::add_created_by_annotation(
+		&config.created_by,
+		created_by.as_deref(),
+		obj,
+		&mut patches,
+	);
+	let computed_tags = created_by
+		.map(|created_by| (config.created_by.tag.clone(), created_by))
+		.into_iter()
+		.chain(mutators::source_tags(&config.tags, obj))
+		.collect();
+	let owned_tags = mutators::add_tags(
+		config,
+		team,
+		namespace,
+		computed_tags,
+		obj,
+		&mut patches,
+		&mut warnings,
+	);
+	mutators::validate_tags(config, &owned_tags, obj)?;
+	let cloud = mutators::resolve_cloud_name(config, obj, namespace)?;
+	let cloud_name = mutators::add_location(&config.cloud, cloud, obj, old_obj, &mut patches)?;
+	if !mutators::check_project_vpc_ref(config, cluster, obj)? {
+		mutators::validate_project_vpc_id(config, obj, old_obj)?;
+		mutators::add_project_vpc_id(config, &cloud_name, obj, &mut patches)?;
+	}
+	mutators::add_maintenance_window(&config.maintenance_window, obj, old_obj, &mut patches);
+	mutators::add_backup_time(&config.backup, obj, &mut patches);
+	mutators::validate_version_change(&config.version, obj, old_obj)?;
+	let version = mutators::add_version(&config.version, obj, old_obj, &mut patches);
+	mutators::validate_catalog(
+		&state.catalog,
+		&cloud_name,
+		plan.as_deref(),
+		version.as_deref(),
+		obj,
+		old_obj,
+	)?;
+	mutators::add_conn_info_secret_target(
+		&config.conn_info_secret,
+		&variables,
+		obj,
+		old_obj,
+		&mut patches,
+	);
+	mutators::add_ip_filter(
+		&config.ip_filter,
+		&config.tenant.environment,
+		obj,
+		&mut patches,
+		&mut warnings,
+	)?;
+	mutators::merge_user_config_defaults(&config.user_config, obj, &mut patches);
 
-	mutators::add_project_vpc_id(config.project_vpc_id.clone(), &obj, &mut patches);
-	mutators::add_termination_protection(&obj, &mut patches);
-	mutators::add_tags(config, &obj, &mut patches);
-	mutators::add_location(config.location.clone(), &obj, &mut patches);
-
-	Ok(res.with_patch(Patch(patches))?)
+	let mut res = res.with_patch(Patch(patches))?;
+	if !warnings.is_empty() {
+		res.warnings = Some(warnings);
+	}
+	Ok(res)
 }
 
 fn bad_request(reason: &str) -> (StatusCode, Json<AdmissionReview<DynamicObject>>) {
@@ -143,6 +241,7 @@ fn bad_request(reason: &str) -> (StatusCode, Json<AdmissionReview<DynamicObject>
 
 #[cfg(test)]
 mod tests {
+	use std::collections::BTreeMap;
 	use std::fs::File;
 	use std::io::BufReader;
 	use std::path::PathBuf;
@@ -150,19 +249,38 @@ mod tests {
 
 	use axum_test::TestServer;
 	use json_patch::{Patch, PatchOperation};
+	use jsonptr::PointerBuf;
+	use k8s_openapi::api::core::v1::Namespace;
+	use kube::api::ObjectMeta;
 	use kube::core::admission::AdmissionReview;
-	use kube::core::DynamicObject;
+	use kube::core::{DynamicObject, PartialObjectMetaExt};
+	use kube::runtime::{reflector, watcher};
 	use pretty_assertions::assert_eq;
 	use rstest::*;
 	use serde::{Deserialize, Serialize};
 
-	use crate::settings::{AppConfig, LogLevel, Tenant, WebConfig};
-	use crate::web::create_router;
+	use crate::catalog::{Catalog, ServiceCatalog};
+	use crate::cluster::{project_vpc_resource, ClusterState};
+	use crate::settings::{
+		AppConfig, FieldAction, FieldsConfig, ForbiddenField, IpFilterConfig, LogLevel,
+		MetadataConfig, PlanConfig, PolicyAction, Tenant, VersionConfig, WebConfig,
+	};
+	use crate::web::{create_router, AppState};
 
 	#[derive(Serialize, Deserialize, Debug)]
 	pub struct Asserts {
 		status_code: u16,
+		#[serde(default = "allowed_by_default")]
+		allowed: bool,
+		// Substring of the denial message
+		message: Option<String>,
 		patches: Vec<PatchOperation>,
+		#[serde(default)]
+		warnings: Vec<String>,
+	}
+
+	fn allowed_by_default() -> bool {
+		true
 	}
 
 	#[derive(Serialize, Deserialize, Debug)]
@@ -172,8 +290,35 @@ mod tests {
 	}
 
 	#[fixture]
-	pub fn test_server() -> TestServer {
-		let state = Arc::new(AppConfig {
+	pub fn cluster() -> ClusterState {
+		let (namespaces, mut writer) = reflector::store();
+		let shared: ObjectMeta = serde_json::from_value(serde_json::json!({
+			"name": "shared",
+			"labels": {"team": "basseng"}
+		}))
+		.unwrap();
+		writer.apply_watcher_event(&watcher::Event::Apply(
+			shared.into_request_partial::<Namespace>(),
+		));
+		let mut vpc_writer = reflector::store::Writer::new(project_vpc_resource());
+		let project_vpcs = vpc_writer.as_reader();
+		for (name, id) in [
+			("tenant-vpc", "test-vpc-id"),
+			("foreign-vpc", "foreign-vpc-id"),
+		] {
+			let mut vpc = DynamicObject::new(name, &project_vpc_resource()).within("shared");
+			vpc.data = serde_json::json!({"status": {"id": id}});
+			vpc_writer.apply_watcher_event(&watcher::Event::Apply(vpc));
+		}
+		ClusterState {
+			namespaces,
+			project_vpcs: Some(project_vpcs),
+		}
+	}
+
+	#[fixture]
+	pub fn test_server(cluster: ClusterState) -> TestServer {
+		let config = Arc::new(AppConfig {
 			log_format: Default::default(),
 			log_level: LogLevel::Trace,
 			web: WebConfig {
@@ -185,27 +330,107 @@ mod tests {
 				environment: "test-tenant-env".to_string(),
 				name: "test-tenant-name".to_string(),
 			},
+			team: Default::default(),
+			auth_secret: Default::default(),
+			maintenance_window: Default::default(),
+			backup: Default::default(),
+			ip_filter: IpFilterConfig {
+				sets: BTreeMap::from([
+					(
+						"cluster-egress".to_string(),
+						vec!["10.0.0.0/8".parse().unwrap()],
+					),
+					(
+						"onprem".to_string(),
+						vec!["192.168.0.0/16".parse().unwrap()],
+					),
+				]),
+				defaults: Default::default(),
+				policy: PolicyAction::Warn,
+			},
+			plan: PlanConfig {
+				defaults: BTreeMap::from([(
+					"Valkey".to_string(),
+					BTreeMap::from([("test-tenant-env".to_string(), "startup-4".to_string())]),
+				)]),
+			},
+			version: VersionConfig {
+				defaults: BTreeMap::from([("PostgreSQL".to_string(), "17".to_string())]),
+				upgrades: BTreeMap::from([(
+					"PostgreSQL".to_string(),
+					BTreeMap::from([("16".to_string(), vec!["17".to_string()])]),
+				)]),
+			},
+			fields: FieldsConfig {
+				forbidden: BTreeMap::from([(
+					"*".to_string(),
+					vec![ForbiddenField {
+						pointer: PointerBuf::from_tokens(["spec", "userConfig", "public_access"]),
+						action: FieldAction::Remove,
+						reason: None,
+					}],
+				)]),
+			},
+			user_config: Default::default(),
+			tags: Default::default(),
+			created_by: Default::default(),
+			metadata: MetadataConfig {
+				labels: BTreeMap::from([
+					("team".to_string(), "{team}".to_string()),
+					(
+						"app.kubernetes.io/managed-by".to_string(),
+						"mutilator".to_string(),
+					),
+				]),
+			},
+			conn_info_secret: Default::default(),
 			project_vpc_id: "test-vpc-id".to_string(),
-			location: "test-location".to_string(),
+			vpc: Default::default(),
+			location: "europe-north1".to_string(),
+			cloud: Default::default(),
+			catalog_path: None,
 			otel_enabled: false,
 		});
-		let router = create_router(state);
+		let catalog = Arc::new(Catalog {
+			version: "test".to_string(),
+			clouds: Default::default(),
+			services: BTreeMap::from([
+				(
+					"PostgreSQL".to_string(),
+					ServiceCatalog {
+						plans: Default::default(),
+						versions: ["15", "16", "17"].map(String::from).into(),
+					},
+				),
+				(
+					"Valkey".to_string(),
+					ServiceCatalog {
+						plans: ["hobbyist", "startup-4"].map(String::from).into(),
+						versions: Default::default(),
+					},
+				),
+			]),
+		});
+		let router = create_router(AppState {
+			config,
+			cluster,
+			catalog,
+		});
 		TestServer::new(router.into_make_service()).unwrap()
 	}
 
 	#[fixture]
 	pub fn test_dir() -> PathBuf {
-		let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/test_data/");
-		root
+		PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/test_data/")
 	}
 
 	fn test_data(path: PathBuf, file_name: &str) -> TestData {
 		let file_path = path.join(file_name);
 		serde_json::from_reader(BufReader::new(
 			File::open(file_path.clone())
-				.expect(format!("Unable to read '{}'", file_path.display()).as_str()),
+				.unwrap_or_else(|_| panic!("Unable to read '{}'", file_path.display())),
 		))
-		.expect(format!("Unable to deserialize '{}'", file_path.display()).as_str())
+		.unwrap_or_else(|_| panic!("Unable to deserialize '{}'", file_path.display()))
 	}
 
 	#[rstest]
@@ -213,12 +438,25 @@ mod tests {
 	#[case("golden_opensearch.json")]
 	#[case("valkey_with_all_tags.json")]
 	#[case("ignoring_kafka.json")]
+	#[case("valkey_with_nested_tag.json")]
+	#[case("valkey_in_labelled_namespace.json")]
+	#[case("valkey_with_project_vpc_ref.json")]
+	#[case("valkey_with_foreign_project_vpc_ref.json")]
+	#[case("valkey_with_unknown_project_vpc_id.json")]
+	#[case("valkey_with_foreign_project_vpc_id.json")]
+	#[case("valkey_with_unknown_plan.json")]
+	#[case("golden_postgresql.json")]
+	#[case("golden_mysql.json")]
+	#[case("valkey_with_ip_filter_annotation.json")]
+	#[case("valkey_without_plan.json")]
+	#[case("postgresql_version_downgrade.json")]
+	#[case("valkey_with_forbidden_field.json")]
 	#[tokio::test]
 	async fn test_mutate(test_server: TestServer, test_dir: PathBuf, #[case] file_name: &str) {
 		let test_data = test_data(test_dir, file_name);
 		let resp = test_server
 			.post("/mutate")
-			.content_type(&"application/json")
+			.content_type("application/json")
 			.json(&test_data.admission_review)
 			.await;
 		assert_eq!(
@@ -229,9 +467,24 @@ mod tests {
 		let admission_result: AdmissionReview<DynamicObject> = resp.json();
 		let admission_response = admission_result.response.as_ref().unwrap();
 		println!("{:?}", &admission_result);
-		assert!(admission_response.allowed, "Result should be allowed");
+		assert_eq!(
+			admission_response.allowed, test_data.asserts.allowed,
+			"Unexpected admission result"
+		);
+		if let Some(message) = &test_data.asserts.message {
+			assert!(
+				admission_response.result.message.contains(message.as_str()),
+				"Expected message containing {message:?}, got {:?}",
+				admission_response.result.message
+			);
+		}
+		assert_eq!(
+			admission_response.warnings.clone().unwrap_or_default(),
+			test_data.asserts.warnings,
+			"Unexpected warnings"
+		);
 		let patch = admission_response.patch.as_ref();
-		if test_data.asserts.patches.len() > 0 {
+		if !test_data.asserts.patches.is_empty() {
 			assert!(patch.is_some(), "Expected patch, but got none");
 			let patches: Patch = serde_json::from_slice(patch.unwrap().as_slice()).unwrap();
 			assert_eq!(